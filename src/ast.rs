@@ -3,19 +3,28 @@ use crate::{operator::Operator, value::Value};
 #[derive(Debug, PartialEq)]
 pub enum Node {
     Value(Value),
+    DefaultRoll(u64), // Quantity of dice of the default size.
     Identifier(String),
     List(Vec<usize>),
+    Map(Vec<(usize, usize)>),
+    Index(usize, usize), // Target, index or key.
     Call(String, Vec<usize>),
     Binary(usize, Operator, usize),
     Unary(usize, Operator),
     If(usize, usize, Option<usize>), // Condition, block if true, optional else.
+    Try(usize, usize),               // Expression, fallback if it errors.
+    Match(usize, Vec<(Option<usize>, usize)>), // Scrutinee, (pattern or _, result).
     Import(usize),
+    Memo(usize),                // Function definition to memoise.
+    Module(String, Vec<usize>), // Name, statements.
+    Block(Vec<usize>),          // Statements, evaluated in a child scope.
 }
 
 impl Node {
     fn copy(&self, from: &Ast, to: &mut Ast) -> Option<usize> {
         match self {
             Node::Value(val) => Some(to.add(Self::Value(val.clone()))),
+            &Node::DefaultRoll(quantity) => Some(to.add(Self::DefaultRoll(quantity))),
             Node::Identifier(name) => Some(to.add(Self::Identifier(name.clone()))),
             Node::List(values) => {
                 let mut new_vals = Vec::new();
@@ -24,6 +33,15 @@ impl Node {
                 }
                 Some(to.add(Self::List(new_vals)))
             }
+            Node::Map(entries) => {
+                let mut new_entries = Vec::new();
+                for &(key, value) in entries {
+                    let key = from.get(key)?.copy(from, to)?;
+                    let value = from.get(value)?.copy(from, to)?;
+                    new_entries.push((key, value));
+                }
+                Some(to.add(Self::Map(new_entries)))
+            }
             Node::Call(name, args) => {
                 let mut new_args = Vec::new();
                 for &arg in args {
@@ -31,6 +49,11 @@ impl Node {
                 }
                 Some(to.add(Node::Call(name.clone(), new_args)))
             }
+            &Node::Index(target, index) => {
+                let target = from.get(target)?.copy(from, to)?;
+                let index = from.get(index)?.copy(from, to)?;
+                Some(to.add(Self::Index(target, index)))
+            }
             &Node::Binary(lhs, op, rhs) => {
                 let lhs = from.get(lhs)?.copy(from, to)?;
                 let rhs = from.get(rhs)?.copy(from, to)?;
@@ -48,10 +71,46 @@ impl Node {
                     .and_then(|n| n.copy(from, to));
                 Some(to.add(Self::If(cond, expr, fail)))
             }
+            &Node::Try(expr, fallback) => {
+                let expr = from.get(expr)?.copy(from, to)?;
+                let fallback = from.get(fallback)?.copy(from, to)?;
+                Some(to.add(Self::Try(expr, fallback)))
+            }
+            Node::Match(scrutinee, arms) => {
+                let scrutinee = from.get(*scrutinee)?.copy(from, to)?;
+                let mut new_arms = Vec::new();
+                for &(pattern, result) in arms {
+                    let pattern = match pattern {
+                        Some(pattern) => Some(from.get(pattern)?.copy(from, to)?),
+                        None => None,
+                    };
+                    let result = from.get(result)?.copy(from, to)?;
+                    new_arms.push((pattern, result));
+                }
+                Some(to.add(Node::Match(scrutinee, new_arms)))
+            }
             &Node::Import(name) => {
                 let name = from.get(name)?.copy(from, to)?;
                 Some(to.add(Node::Import(name)))
             }
+            &Node::Memo(definition) => {
+                let definition = from.get(definition)?.copy(from, to)?;
+                Some(to.add(Node::Memo(definition)))
+            }
+            Node::Module(name, body) => {
+                let mut new_body = Vec::new();
+                for &statement in body {
+                    new_body.push(from.get(statement)?.copy(from, to)?);
+                }
+                Some(to.add(Node::Module(name.clone(), new_body)))
+            }
+            Node::Block(statements) => {
+                let mut new_statements = Vec::new();
+                for &statement in statements {
+                    new_statements.push(from.get(statement)?.copy(from, to)?);
+                }
+                Some(to.add(Node::Block(new_statements)))
+            }
         }
     }
 
@@ -62,31 +121,45 @@ impl Node {
 }
 
 #[derive(Debug)]
-pub struct Ast(Vec<Node>);
+pub struct Ast {
+    nodes: Vec<Node>,
+    comment: Option<String>, // Comment following the expression in a tome.
+}
 
 impl Ast {
     pub fn new() -> Self {
-        Self(Vec::new())
+        Self {
+            nodes: Vec::new(),
+            comment: None,
+        }
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    pub fn set_comment(&mut self, comment: Option<String>) {
+        self.comment = comment;
     }
 
     pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+        self.nodes.is_empty()
     }
 
     pub fn add(&mut self, expr: Node) -> usize {
-        self.0.push(expr);
-        self.0.len() - 1
+        self.nodes.push(expr);
+        self.nodes.len() - 1
     }
 
     pub fn get(&self, expr: usize) -> Option<&Node> {
-        self.0.get(expr)
+        self.nodes.get(expr)
     }
 
     pub fn start(&self) -> usize {
-        if self.0.is_empty() {
+        if self.nodes.is_empty() {
             0
         } else {
-            self.0.len() - 1
+            self.nodes.len() - 1
         }
     }
 
@@ -98,19 +171,205 @@ impl Ast {
 
     #[cfg(test)]
     pub fn exprs(&self) -> &[Node] {
-        &self.0
+        &self.nodes
+    }
+
+    /// Split a sequence of expressions separated by ; into separate trees.
+    pub fn statements(&self) -> Vec<Ast> {
+        self.sequence(self.start())
+            .into_iter()
+            .filter_map(|id| self.subtree(id))
+            .collect()
+    }
+
+    /// Nodes of each expression in a sequence separated by ;, in order.
+    pub fn sequence(&self, id: usize) -> Vec<usize> {
+        let mut sequence = Vec::new();
+        self._sequence(id, &mut sequence);
+        sequence
+    }
+
+    fn _sequence(&self, id: usize, sequence: &mut Vec<usize>) {
+        if let Some(&Node::Binary(lhs, Operator::Discard, rhs)) = self.get(id) {
+            self._sequence(lhs, sequence);
+            self._sequence(rhs, sequence);
+        } else {
+            sequence.push(id);
+        }
     }
 
     pub fn render(&self) -> String {
         self._render(self.start())
     }
 
+    pub fn render_node(&self, id: usize) -> String {
+        self._render(id)
+    }
+
+    pub fn debug_tree(&self) -> String {
+        let mut tree = String::new();
+        self._debug_tree(self.start(), 0, &mut tree);
+        tree.truncate(tree.trim_end().len());
+        tree
+    }
+
+    fn _debug_tree(&self, id: usize, depth: usize, tree: &mut String) {
+        fn line(tree: &mut String, depth: usize, label: &str) {
+            tree.push_str(&"  ".repeat(depth));
+            tree.push_str(label);
+            tree.push('\n');
+        }
+
+        let Some(node) = self.get(id) else {
+            line(tree, depth, "ERROR");
+            return;
+        };
+
+        let children = match node {
+            Node::Value(_) => {
+                line(tree, depth, &format!("Value {}", self._render(id)));
+                Vec::new()
+            }
+            Node::DefaultRoll(_) => {
+                line(tree, depth, &format!("DefaultRoll {}", self._render(id)));
+                Vec::new()
+            }
+            Node::Identifier(name) => {
+                line(tree, depth, &format!("Identifier {name}"));
+                Vec::new()
+            }
+            Node::List(values) => {
+                line(tree, depth, "List");
+                values.clone()
+            }
+            Node::Map(entries) => {
+                line(tree, depth, "Map");
+                for &(key, value) in entries {
+                    line(tree, depth + 1, "Entry");
+                    self._debug_tree(key, depth + 2, tree);
+                    self._debug_tree(value, depth + 2, tree);
+                }
+                Vec::new()
+            }
+            &Node::Index(target, index) => {
+                line(tree, depth, "Index");
+                vec![target, index]
+            }
+            Node::Call(name, args) => {
+                line(tree, depth, &format!("Call {name}"));
+                args.clone()
+            }
+            &Node::Binary(lhs, op, rhs) => {
+                line(tree, depth, &format!("Binary {}", op.str()));
+                vec![lhs, rhs]
+            }
+            &Node::Unary(arg, op) => {
+                line(tree, depth, &format!("Unary {}", op.str()));
+                vec![arg]
+            }
+            &Node::If(cond, expr, fail) => {
+                line(tree, depth, "If");
+                let mut children = vec![cond, expr];
+                children.extend(fail);
+                children
+            }
+            &Node::Try(expr, fallback) => {
+                line(tree, depth, "Try");
+                vec![expr, fallback]
+            }
+            Node::Match(scrutinee, arms) => {
+                line(tree, depth, "Match");
+                self._debug_tree(*scrutinee, depth + 1, tree);
+                for &(pattern, result) in arms {
+                    line(tree, depth + 1, "Arm");
+                    match pattern {
+                        Some(pattern) => self._debug_tree(pattern, depth + 2, tree),
+                        None => line(tree, depth + 2, "_"),
+                    }
+                    self._debug_tree(result, depth + 2, tree);
+                }
+                Vec::new()
+            }
+            &Node::Import(name) => {
+                line(tree, depth, "Import");
+                vec![name]
+            }
+            &Node::Memo(definition) => {
+                line(tree, depth, "Memo");
+                vec![definition]
+            }
+            Node::Module(name, body) => {
+                line(tree, depth, &format!("Module {name}"));
+                body.clone()
+            }
+            Node::Block(statements) => {
+                line(tree, depth, "Block");
+                statements.clone()
+            }
+        };
+
+        for child in children {
+            self._debug_tree(child, depth + 1, tree);
+        }
+    }
+
+    /// Precedence of the operator at the root of a node, if it has one.
+    fn precedence(&self, id: usize) -> Option<u8> {
+        match self.get(id)? {
+            Node::Binary(_, op, _) | Node::Unary(_, op) => Some(op.precedence()),
+            _ => None,
+        }
+    }
+
+    /// Whether a node ends with an expression which consumes everything after
+    /// it, as an if or try does.
+    fn open_ended(&self, id: usize) -> bool {
+        matches!(self.get(id), Some(Node::If(..) | Node::Try(..)))
+    }
+
+    /// Render an operand of an operator, parenthesised if it would otherwise
+    /// be parsed differently. An if consumes everything after it, so is only
+    /// left bare as the last operand of an expression.
+    fn _render_operand(&self, id: usize, parent: Operator, right: bool, tail: bool) -> String {
+        let parenthesise = match (self.open_ended(id), self.precedence(id)) {
+            (true, _) => !(right && tail && parent.is_binary()),
+            (_, Some(precedence)) => {
+                precedence < parent.precedence()
+                    || (precedence == parent.precedence() && parent.left_associative() == right)
+            }
+            _ => false,
+        };
+
+        if parenthesise {
+            format!("({})", self._render_at(id, true))
+        } else {
+            self._render_at(id, right && tail)
+        }
+    }
+
+    /// Render a block of an if or try, parenthesised if it is itself an if or
+    /// try.
+    fn _render_block(&self, id: usize, tail: bool) -> String {
+        if self.open_ended(id) {
+            format!("({})", self._render_at(id, true))
+        } else {
+            self._render_at(id, tail)
+        }
+    }
+
     fn _render(&self, id: usize) -> String {
+        self._render_at(id, true)
+    }
+
+    /// Render a node. The tail flag indicates that nothing will follow the
+    /// node without a delimiter.
+    fn _render_at(&self, id: usize, tail: bool) -> String {
         if let Some(node) = self.get(id) {
             match node {
                 Node::Value(Value::Outcome(oc)) => format!("{}", oc.roll),
                 Node::Value(Value::Empty) => "ERROR".to_string(),
                 Node::Value(v) => format!("{v}"),
+                Node::DefaultRoll(quantity) => format!("{quantity}d"),
                 Node::Identifier(name) => name.clone(),
                 Node::List(values) => {
                     format!(
@@ -124,11 +383,35 @@ impl Ast {
                         })
                     )
                 }
+                Node::Map(entries) => {
+                    format!(
+                        "{{{}}}",
+                        entries
+                            .iter()
+                            .fold(String::new(), |mut acc, &(key, value)| {
+                                if !acc.is_empty() {
+                                    acc.push_str(", ");
+                                }
+                                acc.push_str(&self._render(key));
+                                acc.push_str(": ");
+                                acc.push_str(&self._render(value));
+                                acc
+                            })
+                    )
+                }
+                &Node::Index(target, index) => {
+                    format!("{}[{}]", self._render(target), self._render(index))
+                }
                 &Node::Binary(lhs, op, rhs) => {
-                    format!("{} {} {}", self._render(lhs), op.str(), self._render(rhs))
+                    format!(
+                        "{} {} {}",
+                        self._render_operand(lhs, op, false, false),
+                        op.str(),
+                        self._render_operand(rhs, op, true, tail)
+                    )
                 }
                 &Node::Unary(arg, op) => {
-                    let arg = self._render(arg);
+                    let arg = self._render_operand(arg, op, !op.is_unary_postfix(), tail);
                     if op.is_unary_postfix() {
                         format!("{}{}", arg, op.str())
                     } else {
@@ -148,18 +431,79 @@ impl Ast {
                     )
                 }
                 &Node::If(cond, expr, fail) => {
-                    if let Some(node) = fail {
+                    let cond = self._render_block(cond, false);
+                    if let Some(fail) = fail
+                        && let Some(Node::If(..)) = self.get(fail)
+                    {
                         format!(
-                            "if ({}) then ({}) else ({})",
-                            self._render(cond),
-                            self._render(expr),
-                            self._render(node)
+                            "if {cond} then {} el{}",
+                            self._render_block(expr, false),
+                            self._render_at(fail, tail)
+                        )
+                    } else if let Some(fail) = fail {
+                        format!(
+                            "if {cond} then {} else {}",
+                            self._render_block(expr, false),
+                            self._render_at(fail, tail)
                         )
                     } else {
-                        format!("if ({}) then ({})", self._render(cond), self._render(expr))
+                        format!("if {cond} then {}", self._render_at(expr, tail))
                     }
                 }
+                &Node::Try(expr, fallback) => format!(
+                    "try {} else {}",
+                    self._render_block(expr, false),
+                    self._render_at(fallback, tail)
+                ),
+                Node::Match(scrutinee, arms) => {
+                    format!(
+                        "match {} {{ {} }}",
+                        self._render(*scrutinee),
+                        arms.iter()
+                            .fold(String::new(), |mut acc, &(pattern, result)| {
+                                if !acc.is_empty() {
+                                    acc.push_str(", ");
+                                }
+                                match pattern {
+                                    Some(pattern) => acc.push_str(&self._render(pattern)),
+                                    None => acc.push('_'),
+                                }
+                                acc.push_str(" -> ");
+                                acc.push_str(&self._render(result));
+                                acc
+                            })
+                    )
+                }
                 &Node::Import(name) => format!("import {}", self._render(name)),
+                &Node::Memo(definition) => format!("memo {}", self._render(definition)),
+                Node::Block(statements) => {
+                    // Each statement but the last is followed by a ;, so is
+                    // parenthesised if it would otherwise extend past it.
+                    let last = statements.len().saturating_sub(1);
+                    let rendered: Vec<String> = statements
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &statement)| {
+                            let extends = self.open_ended(statement)
+                                || self.precedence(statement).is_some_and(|precedence| {
+                                    precedence <= Operator::Discard.precedence()
+                                });
+                            if i < last && extends {
+                                format!("({})", self._render(statement))
+                            } else {
+                                self._render(statement)
+                            }
+                        })
+                        .collect();
+                    format!("{{ {} }}", rendered.join(" ; "))
+                }
+                Node::Module(name, body) => format!(
+                    "module {name} {{ {} }}",
+                    body.iter()
+                        .map(|&statement| self._render(statement))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ),
             }
         } else {
             "ERROR".to_string()
@@ -1,14 +1,53 @@
-use crate::{err, eval, outcome::Outcome, roll::Roll, value::Value, Res};
+use std::cmp::Ordering;
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    context::Context,
+    currency, err, eval,
+    outcome::Outcome,
+    rng,
+    roll::{Roll, RollOutcome},
+    transcript,
+    value::{Value, MAX_LENGTH},
+    Res,
+};
+
+enum Arity {
+    Fixed(usize),
+    Variadic(usize),
+}
+
+impl Arity {
+    fn check(&self, name: &str, args: &[Value]) -> Res<()> {
+        match *self {
+            Arity::Fixed(count) => eval::check_argument_count(name, count, args),
+            Arity::Variadic(count) if args.len() < count => err(format!(
+                "Incorrect number of arguments: {name} expects {self}."
+            )),
+            Arity::Variadic(_) => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Arity::Fixed(count) => write!(f, "{count}"),
+            Arity::Variadic(count) => write!(f, "at least {count}"),
+        }
+    }
+}
 
 struct Builtin {
     name: &'static str,
-    args: usize,
+    args: Arity,
     func: &'static dyn Fn(BuiltinCall) -> Res<Outcome>,
 }
 
 impl Builtin {
     fn call(&self, gfc: BuiltinCall) -> Res<Outcome> {
-        eval::check_argument_count(self.name, self.args, &gfc.args)?;
+        self.args.check(self.name, &gfc.args)?;
         (self.func)(gfc)
     }
 }
@@ -16,6 +55,9 @@ impl Builtin {
 struct BuiltinCall<'a> {
     gf: &'a Builtin,
     args: Vec<Value>,
+    context: &'a mut Context,
+    scope: usize,
+    rolls: &'a mut Vec<RollOutcome>, // Rolls made by functions called.
 }
 
 impl<'a> BuiltinCall<'a> {
@@ -42,6 +84,23 @@ impl<'a> BuiltinCall<'a> {
         self.pop().and_then(Value::list)
     }
 
+    /// Pop a list, or the individual dice of a roll along with the outcome
+    /// of rolling it.
+    fn pop_dice(&mut self) -> Res<(Vec<Value>, Vec<RollOutcome>)> {
+        match self.pop()? {
+            roll @ Value::Roll(_) => {
+                let (outcome, dice) = Outcome::new(roll).rolls()?;
+                let dice = dice.into_iter().map(|d| Value::Natural(d as i64));
+                Ok((dice.collect(), outcome.rolls))
+            }
+            value => Ok((value.list()?, Vec::new())),
+        }
+    }
+
+    fn pop_map(&mut self) -> Res<Vec<(Value, Value)>> {
+        self.pop().and_then(Value::map)
+    }
+
     fn pop_natural(&mut self) -> Res<i64> {
         self.pop().and_then(Value::natural)
     }
@@ -49,93 +108,1157 @@ impl<'a> BuiltinCall<'a> {
     fn pop_string(&mut self) -> Res<String> {
         self.pop().and_then(Value::string)
     }
+
+    fn pop_function(&mut self) -> Res<String> {
+        self.pop().and_then(Value::function)
+    }
+
+    /// Call a function in the scope the builtin was called from. Rolls made
+    /// by the function are added to the outcome of the builtin.
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Res<Value> {
+        let mut outcome = self.context.call(self.scope, name, args)?;
+        self.rolls.append(&mut outcome.rolls);
+        Ok(outcome.value)
+    }
+}
+
+/// Halve a value, rounding down, rolling it first if it is a roll. Integers
+/// are halved exactly, as converting them to decimals loses precision.
+fn halve(mut gfc: BuiltinCall) -> Res<Outcome> {
+    let outcome = Outcome::new(gfc.pop()?);
+    let decimal = match &outcome.value {
+        Value::Tagged(value, _) => matches!(**value, Value::Decimal(_)),
+        value => matches!(value, Value::Decimal(_)),
+    };
+    let (mut outcome, half) = if decimal {
+        let (outcome, value) = outcome.decimal()?;
+        (outcome, Value::Decimal((value / 2.0).floor()).natural()?)
+    } else {
+        let (outcome, value) = outcome.natural()?;
+        (outcome, value.div_euclid(2))
+    };
+    let half = Value::Natural(half);
+    outcome.value = match outcome.value.tag() {
+        Some(tag) => half.tagged(tag.to_string()),
+        None => half,
+    };
+    Ok(outcome)
+}
+
+/// Round a value to a multiple, rounding the quotient with the given function.
+/// The result is an integer if the value and multiple are.
+fn round_to(mut gfc: BuiltinCall, round: fn(f64) -> f64) -> Res<Outcome> {
+    let multiple = gfc.pop_decimal()?;
+    let value = gfc.pop_decimal()?;
+    if multiple == 0.0 {
+        return err("Cannot round to a multiple of zero.");
+    }
+
+    let result = round(value / multiple) * multiple;
+    if value.fract() == 0.0 && multiple.fract() == 0.0 {
+        Value::Decimal(result).natural().map(Outcome::nat)
+    } else {
+        Ok(Outcome::new(Value::Decimal(result)))
+    }
+}
+
+/// Smallest or largest element of a list, or die of a roll, depending on
+/// which ordering is preferred.
+fn extreme(mut gfc: BuiltinCall, preferred: Ordering) -> Res<Outcome> {
+    let (list, rolls) = gfc.pop_dice()?;
+    let mut best: Option<Value> = None;
+    for value in list {
+        best = match best {
+            Some(best) if value.compare(&best)? != preferred => Some(best),
+            _ => Some(value),
+        };
+    }
+
+    match best {
+        Some(value) => {
+            let mut outcome = Outcome::new(value);
+            outcome.rolls = rolls;
+            Ok(outcome)
+        }
+        None => Err(format!("Cannot take the {} of an empty list.", gfc.gf.name)),
+    }
+}
+
+/// Combine the elements of a list or dice of a roll with an arithmetic
+/// operation. Rolls in the list are rolled, and their outcomes kept.
+fn fold(
+    mut gfc: BuiltinCall,
+    initial: i64,
+    op: fn(Outcome, Outcome) -> Res<Outcome>,
+) -> Res<Outcome> {
+    let (list, rolls) = gfc.pop_dice()?;
+    let mut outcome = Outcome::nat(initial);
+    outcome.rolls = rolls;
+    for value in list {
+        outcome = op(outcome, Outcome::new(value))?;
+    }
+    Ok(outcome)
+}
+
+/// Pop a predicate and a list, returning the list and the length of its
+/// longest prefix for which the predicate is true.
+fn prefix_while(gfc: &mut BuiltinCall) -> Res<(Vec<Value>, usize)> {
+    let list = gfc.pop_list()?;
+    let func = gfc.pop_function()?;
+    let mut len = 0;
+    for value in &list {
+        if !gfc.call(&func, vec![value.clone()])?.bool()? {
+            break;
+        }
+        len += 1;
+    }
+    Ok((list, len))
+}
+
+/// Restrict a value to a range. Elements of a list and dice of a roll are each
+/// restricted separately.
+fn clamp(mut gfc: BuiltinCall) -> Res<Outcome> {
+    let hi = gfc.pop()?;
+    let lo = gfc.pop()?;
+    if lo.compare(&hi)? == Ordering::Greater {
+        return err("Lower bound of clamp exceeds upper bound.");
+    }
+
+    let clamp_value = |value: Value| -> Res<Value> {
+        Ok(if value.compare(&lo)? == Ordering::Less {
+            lo.clone()
+        } else if value.compare(&hi)? == Ordering::Greater {
+            hi.clone()
+        } else {
+            value
+        })
+    };
+
+    match gfc.pop()? {
+        dice @ (Value::Roll(_) | Value::Rolls(_) | Value::Outcome(_)) => {
+            let (lo, hi) = (lo.natural()?, hi.natural()?);
+            if hi < 0 {
+                return err("Cannot clamp dice below zero.");
+            }
+            let (outcome, dice) = Outcome::new(dice).rolls()?;
+            Ok(Outcome {
+                value: Value::Rolls(
+                    dice.into_iter()
+                        .map(|d| (d as i64).clamp(lo, hi) as u64)
+                        .collect(),
+                ),
+                rolls: outcome.rolls,
+                label: None,
+            })
+        }
+        Value::List(list) => Ok(Outcome::new(Value::List(
+            list.into_iter().map(clamp_value).collect::<Res<_>>()?,
+        ))),
+        value => Ok(Outcome::new(clamp_value(value)?)),
+    }
+}
+
+fn overflow<T>(name: &str) -> Res<T> {
+    err(format!("Result of {name} is too large."))
+}
+
+/// Product of the k integers counting down from n, or None on overflow.
+fn falling_factorial(n: i64, k: i64) -> Option<i64> {
+    (0..k).try_fold(1i64, |acc, i| acc.checked_mul(n - i))
+}
+
+/// Apply a bitwise operation to two integers.
+fn bitwise(mut gfc: BuiltinCall, f: fn(i64, i64) -> i64) -> Res<Outcome> {
+    let b = gfc.pop_natural()?;
+    let a = gfc.pop_natural()?;
+    Ok(Outcome::nat(f(a, b)))
+}
+
+/// Shift the bits of an integer by a number of places.
+fn shift(mut gfc: BuiltinCall, f: fn(i64, u32) -> Option<i64>) -> Res<Outcome> {
+    let places = gfc.pop_natural()?;
+    let value = gfc.pop_natural()?;
+    match u32::try_from(places)
+        .ok()
+        .and_then(|places| f(value, places))
+    {
+        Some(v) => Ok(Outcome::nat(v)),
+        None => Err(format!("Cannot shift by {places} places.")),
+    }
+}
+
+fn check_choice(n: i64, k: i64) -> Res<()> {
+    if n < 0 || k < 0 {
+        err("Cannot choose from or choose a negative number of items.")
+    } else {
+        Ok(())
+    }
+}
+
+/// Sort values by the corresponding keys, failing if any keys can't be
+/// compared.
+fn sort_by_keys(values: Vec<Value>, keys: Vec<Value>) -> Res<Vec<Value>> {
+    let mut error = None;
+    let mut pairs: Vec<(Value, Value)> = keys.into_iter().zip(values).collect();
+    pairs.sort_by(|(a, _), (b, _)| {
+        a.compare(b).unwrap_or_else(|e| {
+            error.get_or_insert(e);
+            Ordering::Equal
+        })
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(pairs.into_iter().map(|(_, value)| value).collect()),
+    }
+}
+
+/// Population variance of the numeric values of a list.
+fn variance(list: Vec<Value>) -> Res<f64> {
+    if list.is_empty() {
+        return err("Cannot compute the variance of an empty list.");
+    }
+
+    let values = list
+        .into_iter()
+        .map(Value::decimal)
+        .collect::<Res<Vec<f64>>>()?;
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    Ok(values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n)
+}
+
+fn stringify(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        v => v.to_string(),
+    }
+}
+
+/// Convert a value to the string print would output for it.
+fn string(mut gfc: BuiltinCall) -> Res<Outcome> {
+    Ok(Outcome::new(Value::String(stringify(gfc.pop()?))))
 }
 
 const BUILTINS: &[Builtin] = &[
     Builtin {
         name: "ceil",
-        args: 1,
-        func: &|mut gfc| gfc.pop_decimal().map(|v| Outcome::nat(v.ceil() as i64)),
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            Value::Decimal(gfc.pop_decimal()?.ceil())
+                .natural()
+                .map(Outcome::nat)
+        },
     },
     Builtin {
         name: "floor",
-        args: 1,
-        func: &|mut gfc| gfc.pop_decimal().map(|v| Outcome::nat(v.floor() as i64)),
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            Value::Decimal(gfc.pop_decimal()?.floor())
+                .natural()
+                .map(Outcome::nat)
+        },
     },
     Builtin {
-        name: "quantity",
-        args: 1,
-        func: &|mut gfc| gfc.pop_roll().map(|r| Outcome::nat(r.quantity as i64)),
+        name: "round_to",
+        args: Arity::Fixed(2),
+        func: &|gfc| round_to(gfc, f64::round),
     },
     Builtin {
-        name: "get",
-        args: 2,
+        name: "floor_to",
+        args: Arity::Fixed(2),
+        func: &|gfc| round_to(gfc, f64::floor),
+    },
+    Builtin {
+        name: "ceil_to",
+        args: Arity::Fixed(2),
+        func: &|gfc| round_to(gfc, f64::ceil),
+    },
+    Builtin {
+        name: "half",
+        args: Arity::Fixed(1),
+        func: &halve,
+    },
+    Builtin {
+        name: "resist",
+        args: Arity::Fixed(1),
+        func: &halve,
+    },
+    Builtin {
+        name: "int",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| gfc.pop_natural().map(Outcome::nat),
+    },
+    Builtin {
+        name: "float",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| gfc.pop_decimal().map(|v| Outcome::new(Value::Decimal(v))),
+    },
+    Builtin {
+        name: "sin",
+        args: Arity::Fixed(1),
         func: &|mut gfc| {
-            let index = gfc.pop_natural()?;
-            let list = gfc.pop_list()?;
+            gfc.pop_decimal()
+                .map(|v| Outcome::new(Value::Decimal(v.sin())))
+        },
+    },
+    Builtin {
+        name: "cos",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            gfc.pop_decimal()
+                .map(|v| Outcome::new(Value::Decimal(v.cos())))
+        },
+    },
+    Builtin {
+        name: "tan",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            gfc.pop_decimal()
+                .map(|v| Outcome::new(Value::Decimal(v.tan())))
+        },
+    },
+    Builtin {
+        name: "pi",
+        args: Arity::Fixed(0),
+        func: &|_| Ok(Outcome::new(Value::Decimal(std::f64::consts::PI))),
+    },
+    Builtin {
+        name: "e",
+        args: Arity::Fixed(0),
+        func: &|_| Ok(Outcome::new(Value::Decimal(std::f64::consts::E))),
+    },
+    Builtin {
+        name: "stats",
+        args: Arity::Fixed(0),
+        func: &|_| {
+            // Six ability scores, each the highest three of 4d6.
+            let mut scores = Vec::new();
+            let mut rolls = Vec::new();
+            for _ in 0..6 {
+                let roll = Outcome::new(Value::Roll(Roll::new(4, 6)));
+                let (mut outcome, score) = roll.keep(Outcome::nat(3))?.natural()?;
+                scores.push(Value::Natural(score));
+                rolls.append(&mut outcome.rolls);
+            }
+            let mut outcome = Outcome::new(Value::List(scores));
+            outcome.rolls = rolls;
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "flip",
+        args: Arity::Fixed(0),
+        func: &|_| {
+            Ok(Outcome::new(Value::Bool(rng::with(|rng| {
+                rng.gen_bool(0.5)
+            }))))
+        },
+    },
+    Builtin {
+        name: "now",
+        args: Arity::Fixed(0),
+        func: &|_| Ok(Outcome::nat(transcript::now() as i64)),
+    },
+    Builtin {
+        name: "timestamp",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| match u64::try_from(gfc.pop_natural()?) {
+            Ok(seconds) => Ok(Outcome::new(Value::String(transcript::timestamp(seconds)))),
+            Err(_) => err("Cannot format a time before 1970."),
+        },
+    },
+    Builtin {
+        name: "shuffle",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let (mut list, rolls) = gfc.pop_dice()?;
+            rng::with(|rng| list.shuffle(rng));
+            let mut outcome = Outcome::new(Value::List(list));
+            outcome.rolls = rolls;
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "str",
+        args: Arity::Fixed(1),
+        func: &string,
+    },
+    Builtin {
+        name: "format",
+        args: Arity::Variadic(1),
+        func: &|mut gfc| {
+            let args: Vec<Value> = gfc.args.drain(1..).collect();
+            let template = gfc.pop_string()?;
+            let parts: Vec<&str> = template.split("{}").collect();
+            if parts.len() - 1 != args.len() {
+                return err(format!(
+                    "Format string has {} placeholders but got {} values.",
+                    parts.len() - 1,
+                    args.len()
+                ));
+            }
 
-            if index < 0 || index as usize >= list.len() {
-                Err(format!(
-                    "Index {index} of range for list of length {}.",
-                    list.len()
-                ))
+            let mut string = parts[0].to_string();
+            for (value, part) in args.into_iter().zip(&parts[1..]) {
+                string.push_str(&stringify(value));
+                string.push_str(part);
+            }
+            Ok(Outcome::new(Value::String(string)))
+        },
+    },
+    Builtin {
+        name: "parse",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let s = gfc.pop_string()?;
+            let s = s.trim();
+            if let Ok(n) = s.parse::<i64>() {
+                Ok(Outcome::nat(n))
+            } else if let Ok(v) = s.parse::<f64>() {
+                Ok(Outcome::new(Value::Decimal(v)))
             } else {
-                Ok(Outcome::new(list.get(index as usize).cloned().unwrap()))
+                Err(format!("Failed to parse number from \"{s}\"."))
             }
         },
     },
     Builtin {
-        name: "set",
-        args: 3,
+        name: "variance",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| Ok(Outcome::new(Value::Decimal(variance(gfc.pop_list()?)?))),
+    },
+    Builtin {
+        name: "stddev",
+        args: Arity::Fixed(1),
         func: &|mut gfc| {
-            let index = gfc.pop_natural()?;
-            let mut list = gfc.pop_list()?;
-            let value = gfc.pop()?;
+            Ok(Outcome::new(Value::Decimal(
+                variance(gfc.pop_list()?)?.sqrt(),
+            )))
+        },
+    },
+    Builtin {
+        name: "factorial",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let n = gfc.pop_natural()?;
+            if n < 0 {
+                return err("Cannot take the factorial of a negative number.");
+            }
+            match falling_factorial(n, n) {
+                Some(v) => Ok(Outcome::nat(v)),
+                None => overflow("factorial"),
+            }
+        },
+    },
+    Builtin {
+        name: "permutations",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let k = gfc.pop_natural()?;
+            let n = gfc.pop_natural()?;
+            check_choice(n, k)?;
+            if k > n {
+                return Ok(Outcome::nat(0));
+            }
+            match falling_factorial(n, k) {
+                Some(v) => Ok(Outcome::nat(v)),
+                None => overflow("permutations"),
+            }
+        },
+    },
+    Builtin {
+        name: "combinations",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let k = gfc.pop_natural()?;
+            let n = gfc.pop_natural()?;
+            check_choice(n, k)?;
+            if k > n {
+                return Ok(Outcome::nat(0));
+            }
 
-            if index < 0 || index as usize >= list.len() {
-                Err(format!(
-                    "Index {index} of range for list of length {}.",
-                    list.len()
-                ))
-            } else {
-                list[index as usize] = value;
-                Ok(Outcome::new(Value::List(list)))
+            // Multiplying before dividing keeps each step exact, using a
+            // wider type so that the intermediate product cannot overflow.
+            let mut result: i128 = 1;
+            for i in 0..k.min(n - k) as i128 {
+                result = result * (n as i128 - i) / (i + 1);
+                if result > i64::MAX as i128 {
+                    return overflow("combinations");
+                }
             }
+            Ok(Outcome::nat(result as i64))
         },
     },
     Builtin {
-        name: "dice",
-        args: 1,
-        func: &|mut gfc| gfc.pop_roll().map(|r| Outcome::nat(r.die as i64)),
+        name: "band",
+        args: Arity::Fixed(2),
+        func: &|gfc| bitwise(gfc, |a, b| a & b),
     },
     Builtin {
-        name: "print",
-        args: 1,
+        name: "bor",
+        args: Arity::Fixed(2),
+        func: &|gfc| bitwise(gfc, |a, b| a | b),
+    },
+    Builtin {
+        name: "bxor",
+        args: Arity::Fixed(2),
+        func: &|gfc| bitwise(gfc, |a, b| a ^ b),
+    },
+    Builtin {
+        name: "shl",
+        args: Arity::Fixed(2),
+        func: &|gfc| shift(gfc, i64::checked_shl),
+    },
+    Builtin {
+        name: "shr",
+        args: Arity::Fixed(2),
+        func: &|gfc| shift(gfc, i64::checked_shr),
+    },
+    Builtin {
+        name: "quantity",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| gfc.pop_roll().map(|r| Outcome::nat(r.quantity as i64)),
+    },
+    Builtin {
+        name: "roll",
+        args: Arity::Variadic(0),
+        func: &|mut gfc| {
+            let die = match gfc.args.len() {
+                0 | 1 => gfc.context.default_die(),
+                2 => {
+                    let die = gfc.pop_natural()?;
+                    u64::try_from(die).map_err(|_| format!("Cannot roll a d{die}."))?
+                }
+                _ => return err("Incorrect number of arguments: roll expects at most 2."),
+            };
+            let quantity = match gfc.args.len() {
+                0 => 1,
+                _ => {
+                    let quantity = gfc.pop_natural()?;
+                    u64::try_from(quantity).map_err(|_| format!("Cannot roll {quantity} dice."))?
+                }
+            };
+            Ok(Outcome::new(Value::Roll(Roll::new(quantity, die))))
+        },
+    },
+    Builtin {
+        name: "get",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let key = gfc.pop()?;
+            // A roll is rolled, so that its dice are recorded.
+            let mut collection = Outcome::new(gfc.pop()?).resolved()?;
+            collection.value = collection.value.get(key)?;
+            Ok(collection)
+        },
+    },
+    Builtin {
+        name: "set",
+        args: Arity::Fixed(3),
+        func: &|mut gfc| {
+            let key = gfc.pop()?;
+            let collection = gfc.pop()?;
+            let value = gfc.pop()?;
+            collection.set(key, value).map(Outcome::new)
+        },
+    },
+    Builtin {
+        name: "same_result",
+        args: Arity::Fixed(2),
         func: &|mut gfc| {
-            gfc.pop_string().map(|s| {
-                println!("{s}");
-                Outcome::empty()
+            let b = Outcome::new(gfc.pop()?).resolved()?;
+            let mut a = Outcome::new(gfc.pop()?).resolved()?;
+            a.rolls.extend(b.rolls);
+            Ok(Outcome {
+                value: Value::Bool(a.value.same_result(&b.value)),
+                rolls: a.rolls,
+                label: None,
             })
         },
     },
-];
+    Builtin {
+        name: "keys",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let keys = gfc.pop_map()?.into_iter().map(|(k, _)| k).collect();
+            Ok(Outcome::new(Value::List(keys)))
+        },
+    },
+    Builtin {
+        name: "values",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let values = gfc.pop_map()?.into_iter().map(|(_, v)| v).collect();
+            Ok(Outcome::new(Value::List(values)))
+        },
+    },
+    Builtin {
+        name: "range",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let end = gfc.pop_natural()?;
+            let start = gfc.pop_natural()?;
+            Ok(Outcome::new(Value::range(start, end)?))
+        },
+    },
+    Builtin {
+        name: "repeat",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let times = gfc.pop_natural()?;
+            let value = gfc.pop()?;
+            if times > MAX_LENGTH {
+                return Err(format!(
+                    "Cannot repeat a value {times} times, at most {MAX_LENGTH} copies can be made."
+                ));
+            }
+            let times = usize::try_from(times)
+                .map_err(|_| format!("Cannot repeat a value {times} times."))?;
+            Ok(Outcome::new(Value::List(vec![value; times])))
+        },
+    },
+    Builtin {
+        name: "sum",
+        args: Arity::Fixed(1),
+        func: &|gfc| fold(gfc, 0, Outcome::add),
+    },
+    Builtin {
+        name: "product",
+        args: Arity::Fixed(1),
+        func: &|gfc| fold(gfc, 1, Outcome::mul),
+    },
+    Builtin {
+        name: "sort",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let (list, rolls) = gfc.pop_dice()?;
+            let sorted = sort_by_keys(list.clone(), list)?;
+            let mut outcome = Outcome::new(Value::List(sorted));
+            outcome.rolls = rolls;
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "sort_desc",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let (list, rolls) = gfc.pop_dice()?;
+            let mut sorted = sort_by_keys(list.clone(), list)?;
+            sorted.reverse();
+            let mut outcome = Outcome::new(Value::List(sorted));
+            outcome.rolls = rolls;
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "sort_by",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let func = gfc.pop_function()?;
+            let list = gfc.pop_list()?;
+            let keys = list
+                .iter()
+                .map(|v| gfc.call(&func, vec![v.clone()]))
+                .collect::<Res<Vec<Value>>>()?;
+            Ok(Outcome::new(Value::List(sort_by_keys(list, keys)?)))
+        },
+    },
+    Builtin {
+        name: "min",
+        args: Arity::Fixed(1),
+        func: &|gfc| extreme(gfc, Ordering::Less),
+    },
+    Builtin {
+        name: "max",
+        args: Arity::Fixed(1),
+        func: &|gfc| extreme(gfc, Ordering::Greater),
+    },
+    Builtin {
+        name: "count",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let target = gfc.pop()?;
+            let (list, rolls) = gfc.pop_dice()?;
+            let mut count = 0;
+            for value in list {
+                let matches = match &target {
+                    Value::Function(func) => gfc.call(func, vec![value])?.bool()?,
+                    target => value == *target,
+                };
+                if matches {
+                    count += 1;
+                }
+            }
+            let mut outcome = Outcome::nat(count);
+            outcome.rolls = rolls;
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "reroll_if",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let func = gfc.pop_function()?;
+            let roll = gfc.pop_roll()?;
+            if roll.takes_highest() || roll.takes_lowest() {
+                return err("Cannot reroll a roll with advantage or disadvantage.");
+            }
 
-pub fn call(name: &str, args: Vec<Value>) -> Res<Outcome> {
-    for gf in BUILTINS {
-        if gf.name == name {
-            return gf.call(BuiltinCall { gf, args });
-        }
-    }
-    err(format!("Undefined function: {name}."))
-}
+            let die = roll.die;
+            let (mut outcome, mut dice) = Outcome::new(Value::Roll(roll)).rolls()?;
+            let mut rerolls = Vec::new();
+            for d in dice.iter_mut() {
+                if gfc.call(&func, vec![Value::Natural(*d as i64)])?.bool()? {
+                    *d = rng::roll(die);
+                    rerolls.push(*d);
+                }
+            }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+            // Record the rerolled dice as a roll of their own.
+            if !rerolls.is_empty() {
+                outcome.rolls.push(RollOutcome {
+                    roll: Roll::new(rerolls.len() as u64, die),
+                    result: rerolls.iter().sum(),
+                    rolls: rerolls,
+                });
+            }
+            outcome.value = Value::Rolls(dice);
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "pool",
+        args: Arity::Fixed(3),
+        func: &|mut gfc| {
+            let target = gfc.pop_natural()?;
+            let sides = gfc.pop_natural()?;
+            let quantity = gfc.pop_natural()?;
+            let (Ok(quantity), Ok(sides)) = (u64::try_from(quantity), u64::try_from(sides)) else {
+                return err("Cannot roll a negative number of dice or sides.");
+            };
 
-    #[test]
-    fn test_ceil() {
-        assert_eq!(
+            let roll = Outcome::new(Value::Roll(Roll::new(quantity, sides)));
+            let (mut outcome, dice) = roll.rolls()?;
+            let successes = dice.iter().filter(|&&d| d as i64 >= target).count();
+            let botches = dice.iter().filter(|&&d| d == 1).count();
+            outcome.value = Value::Map(vec![
+                (
+                    Value::String("successes".into()),
+                    Value::Natural(successes as i64),
+                ),
+                (
+                    Value::String("botches".into()),
+                    Value::Natural(botches as i64),
+                ),
+                (Value::String("rolls".into()), Value::Rolls(dice)),
+            ]);
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "clamp",
+        args: Arity::Fixed(3),
+        func: &clamp,
+    },
+    Builtin {
+        name: "flatten",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let mut flat = Vec::new();
+            for value in gfc.pop_list()? {
+                match value {
+                    Value::List(values) => flat.extend(values),
+                    value => flat.push(value),
+                }
+            }
+            Ok(Outcome::new(Value::List(flat)))
+        },
+    },
+    Builtin {
+        name: "zip",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let b = gfc.pop_list()?;
+            let a = gfc.pop_list()?;
+            Ok(Outcome::new(Value::List(
+                a.into_iter()
+                    .zip(b)
+                    .map(|(a, b)| Value::List(vec![a, b]))
+                    .collect(),
+            )))
+        },
+    },
+    Builtin {
+        name: "zip_with",
+        args: Arity::Fixed(3),
+        func: &|mut gfc| {
+            let b = gfc.pop_list()?;
+            let a = gfc.pop_list()?;
+            let func = gfc.pop_function()?;
+            let mut results = Vec::new();
+            for (a, b) in a.into_iter().zip(b) {
+                results.push(gfc.call(&func, vec![a, b])?);
+            }
+            Ok(Outcome::new(Value::List(results)))
+        },
+    },
+    Builtin {
+        name: "group_by",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let list = gfc.pop_list()?;
+            let func = gfc.pop_function()?;
+            let mut groups: Vec<(Value, Value)> = Vec::new();
+            for value in list {
+                let key = gfc.call(&func, vec![value.clone()])?;
+                match groups.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, Value::List(group))) => group.push(value),
+                    _ => groups.push((key, Value::List(vec![value]))),
+                }
+            }
+            Ok(Outcome::new(Value::Map(groups)))
+        },
+    },
+    Builtin {
+        name: "enumerate",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            Ok(Outcome::new(Value::List(
+                gfc.pop_list()?
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| Value::List(vec![Value::Natural(i as i64), v]))
+                    .collect(),
+            )))
+        },
+    },
+    Builtin {
+        name: "take",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let n = gfc.pop_natural()?;
+            let mut list = gfc.pop_list()?;
+            list.truncate(n.clamp(0, list.len() as i64) as usize);
+            Ok(Outcome::new(Value::List(list)))
+        },
+    },
+    Builtin {
+        name: "drop",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let n = gfc.pop_natural()?;
+            let mut list = gfc.pop_list()?;
+            list.drain(..n.clamp(0, list.len() as i64) as usize);
+            Ok(Outcome::new(Value::List(list)))
+        },
+    },
+    Builtin {
+        name: "take_while",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let (mut list, len) = prefix_while(&mut gfc)?;
+            list.truncate(len);
+            Ok(Outcome::new(Value::List(list)))
+        },
+    },
+    Builtin {
+        name: "drop_while",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let (mut list, len) = prefix_while(&mut gfc)?;
+            list.drain(..len);
+            Ok(Outcome::new(Value::List(list)))
+        },
+    },
+    Builtin {
+        name: "all",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            for value in gfc.pop_list()? {
+                if !value.bool()? {
+                    return Ok(Outcome::new(Value::Bool(false)));
+                }
+            }
+            Ok(Outcome::new(Value::Bool(true)))
+        },
+    },
+    Builtin {
+        name: "any",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            for value in gfc.pop_list()? {
+                if value.bool()? {
+                    return Ok(Outcome::new(Value::Bool(true)));
+                }
+            }
+            Ok(Outcome::new(Value::Bool(false)))
+        },
+    },
+    Builtin {
+        name: "startswith",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let prefix = gfc.pop_string()?;
+            let string = gfc.pop_string()?;
+            Ok(Outcome::new(Value::Bool(string.starts_with(&prefix))))
+        },
+    },
+    Builtin {
+        name: "endswith",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let suffix = gfc.pop_string()?;
+            let string = gfc.pop_string()?;
+            Ok(Outcome::new(Value::Bool(string.ends_with(&suffix))))
+        },
+    },
+    Builtin {
+        name: "contains",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let item = gfc.pop()?;
+            let contains = match gfc.pop()? {
+                Value::String(string) => string.contains(&item.string()?),
+                collection => collection.list()?.contains(&item),
+            };
+            Ok(Outcome::new(Value::Bool(contains)))
+        },
+    },
+    Builtin {
+        name: "split",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let separator = gfc.pop_string()?;
+            let string = gfc.pop_string()?;
+            if separator.is_empty() {
+                return Ok(Outcome::new(Value::List(Value::String(string).list()?)));
+            }
+
+            Ok(Outcome::new(Value::List(
+                string
+                    .split(&separator)
+                    .map(|s| Value::String(s.to_string()))
+                    .collect(),
+            )))
+        },
+    },
+    Builtin {
+        name: "join",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let separator = gfc.pop_string()?;
+            let strings = gfc
+                .pop_list()?
+                .into_iter()
+                .map(Value::string)
+                .collect::<Res<Vec<String>>>()?;
+            Ok(Outcome::new(Value::String(strings.join(&separator))))
+        },
+    },
+    Builtin {
+        name: "upper",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            Ok(Outcome::new(Value::String(
+                gfc.pop_string()?.to_uppercase(),
+            )))
+        },
+    },
+    Builtin {
+        name: "lower",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            Ok(Outcome::new(Value::String(
+                gfc.pop_string()?.to_lowercase(),
+            )))
+        },
+    },
+    Builtin {
+        name: "gain",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let denomination = gfc.pop_string()?;
+            let amount = gfc.pop_natural()?;
+            currency::gain(gfc.context, amount, &denomination).map(Outcome::nat)
+        },
+    },
+    Builtin {
+        name: "spend",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let denomination = gfc.pop_string()?;
+            let amount = gfc.pop_natural()?;
+            currency::spend(gfc.context, amount, &denomination)
+                .map(|spent| Outcome::new(Value::Bool(spent)))
+        },
+    },
+    Builtin {
+        name: "choose",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let mut list = gfc.pop_list()?;
+            if list.is_empty() {
+                err("Cannot choose from an empty list.")
+            } else {
+                let index = rng::with(|rng| rng.gen_range(0..list.len()));
+                Ok(Outcome::new(list.swap_remove(index)))
+            }
+        },
+    },
+    Builtin {
+        name: "weighted",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let weights = gfc
+                .pop_list()?
+                .into_iter()
+                .map(Value::decimal)
+                .collect::<Res<Vec<f64>>>()?;
+            let mut values = gfc.pop_list()?;
+            if values.len() != weights.len() {
+                return err(format!(
+                    "Expected {} weights but got {}.",
+                    values.len(),
+                    weights.len()
+                ));
+            }
+            if weights.iter().any(|w| *w < 0.0) {
+                return err("Weights cannot be negative.");
+            }
+
+            let total: f64 = weights.iter().sum();
+            if total <= 0.0 {
+                return err("Weights must not all be zero.");
+            }
+            if !total.is_finite() {
+                return err("Weights are too large to sum.");
+            }
+
+            let mut target = rng::with(|rng| rng.gen_range(0.0..total));
+            let mut index = values.len() - 1;
+            for (i, weight) in weights.iter().enumerate() {
+                if target < *weight {
+                    index = i;
+                    break;
+                }
+                target -= weight;
+            }
+            Ok(Outcome::new(values.swap_remove(index)))
+        },
+    },
+    Builtin {
+        name: "dice",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| gfc.pop_roll().map(|r| Outcome::nat(r.die as i64)),
+    },
+    Builtin {
+        name: "print",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            println!("{}", stringify(gfc.pop()?));
+            Ok(Outcome::empty())
+        },
+    },
+    Builtin {
+        name: "assert",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| {
+            let value = gfc.pop()?;
+            if value.clone().bool()? {
+                Ok(Outcome::empty())
+            } else {
+                err(format!("Assertion failed: {value} is false."))
+            }
+        },
+    },
+    Builtin {
+        name: "assert_eq",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let rhs = gfc.pop()?;
+            let lhs = gfc.pop()?;
+            if lhs == rhs {
+                Ok(Outcome::empty())
+            } else {
+                err(format!("Assertion failed: {lhs} != {rhs}."))
+            }
+        },
+    },
+    Builtin {
+        name: "error",
+        args: Arity::Fixed(1),
+        func: &|mut gfc| err(stringify(gfc.pop()?)),
+    },
+    Builtin {
+        name: "fmt",
+        args: Arity::Fixed(1),
+        func: &string,
+    },
+    Builtin {
+        name: "tag",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let tag = gfc.pop_string()?;
+            let mut outcome = Outcome::new(gfc.pop()?).resolved()?;
+            if let Value::Outcome(roll) = outcome.value {
+                outcome.value = Value::Natural(roll.result as i64);
+            }
+            outcome.value = outcome.value.tagged(tag);
+            Ok(outcome)
+        },
+    },
+    Builtin {
+        name: "label",
+        args: Arity::Fixed(2),
+        func: &|mut gfc| {
+            let value = gfc.pop()?;
+            let label = gfc.pop_string()?;
+            Ok(Outcome::new(value).resolved()?.labelled(label))
+        },
+    },
+];
+
+pub fn call(context: &mut Context, scope: usize, name: &str, args: Vec<Value>) -> Res<Outcome> {
+    for gf in BUILTINS {
+        if gf.name == name {
+            let mut rolls = Vec::new();
+            let mut outcome = gf.call(BuiltinCall {
+                gf,
+                args,
+                context,
+                scope,
+                rolls: &mut rolls,
+            })?;
+            rolls.append(&mut outcome.rolls);
+            outcome.rolls = rolls;
+            return Ok(outcome);
+        }
+    }
+    err(format!("Undefined function: {name}."))
+}
+
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.iter().map(|gf| gf.name)
+}
+
+/// Whether there is a builtin of this name which takes arguments.
+pub fn takes_arguments(name: &str) -> bool {
+    BUILTINS
+        .iter()
+        .any(|gf| gf.name == name && !matches!(gf.args, Arity::Fixed(0)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn call(name: &str, args: Vec<Value>) -> Res<Outcome> {
+        super::call(&mut Context::empty(), Context::GLOBAL_SCOPE, name, args)
+    }
+
+    #[test]
+    fn test_ceil() {
+        assert_eq!(
             call("ceil", vec![Value::Decimal(2.5)])
                 .and_then(Outcome::decimal)
                 .map(|tup| tup.1)
@@ -157,6 +1280,922 @@ mod test {
             -2.0
         );
         assert!(call("ceil", vec![Value::Empty]).is_err());
+        for name in ["ceil", "floor"] {
+            assert_eq!(
+                call(name, vec![Value::Decimal(1e300)]),
+                err("Numeric overflow.")
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_to() {
+        let test =
+            |name, value, multiple| call(name, vec![value, multiple]).map(|outcome| outcome.value);
+        let (nat, dec) = (Value::Natural, Value::Decimal);
+        assert_eq!(test("round_to", nat(7), nat(5)), Ok(nat(5)));
+        assert_eq!(test("round_to", nat(8), nat(5)), Ok(nat(10)));
+        assert_eq!(test("floor_to", nat(9), nat(5)), Ok(nat(5)));
+        assert_eq!(test("ceil_to", nat(6), nat(5)), Ok(nat(10)));
+        assert_eq!(test("ceil_to", nat(-6), nat(5)), Ok(nat(-5)));
+        assert_eq!(test("round_to", dec(2.3), dec(0.5)), Ok(dec(2.5)));
+        assert_eq!(test("floor_to", dec(7.5), nat(5)), Ok(dec(5.0)));
+        assert!(test("round_to", nat(7), nat(0)).is_err());
+        assert_eq!(
+            test("round_to", dec(1e300), nat(5)),
+            err("Numeric overflow.")
+        );
+    }
+
+    #[test]
+    fn test_trig() {
+        let test = |name, args| call(name, args).and_then(Outcome::decimal).unwrap().1;
+        assert_eq!(test("sin", vec![Value::Natural(0)]), 0.0);
+        assert_eq!(test("cos", vec![Value::Natural(0)]), 1.0);
+        let pi = test("pi", Vec::new());
+        assert!((pi.cos() + 1.0).abs() < 1e-9);
+        assert!((test("e", Vec::new()).ln() - 1.0).abs() < 1e-9);
+        assert!((test("tan", vec![Value::Decimal(pi / 4.0)]) - 1.0).abs() < 1e-9);
+        assert!(call("sin", vec![Value::String("x".into())]).is_err());
+        assert_eq!(
+            crate::eval("sin(pi / 2)", &mut Context::empty())
+                .unwrap()
+                .value,
+            Value::Decimal(1.0)
+        );
+    }
+
+    #[test]
+    fn test_now() {
+        let now = || call("now", Vec::new()).unwrap().value.natural().unwrap();
+        let first = now();
+        assert!(first > 1_700_000_000);
+        assert!(now() >= first);
+
+        assert_eq!(
+            call("timestamp", vec![Value::Natural(1700000000)])
+                .unwrap()
+                .value,
+            Value::String("2023-11-14 22:13:20".into())
+        );
+        assert!(call("timestamp", vec![Value::Natural(-1)]).is_err());
+    }
+
+    #[test]
+    fn test_int() {
+        assert_eq!(
+            call("int", vec![Value::Decimal(3.9)]).unwrap().value,
+            Value::Natural(3)
+        );
+        assert_eq!(
+            call("int", vec![Value::Decimal(1e300)]),
+            err("Numeric overflow.")
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        assert_eq!(
+            call("float", vec![Value::Natural(4)]).unwrap().value,
+            Value::Decimal(4.0)
+        );
+    }
+
+    #[test]
+    fn test_str() {
+        assert_eq!(
+            call(
+                "str",
+                vec![Value::List(vec![Value::Natural(1), Value::Natural(2)])]
+            )
+            .unwrap()
+            .value,
+            Value::String("[1, 2]".into())
+        );
+        assert_eq!(
+            call("str", vec![Value::String("abc".into())])
+                .unwrap()
+                .value,
+            Value::String("abc".into())
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            call("parse", vec![Value::String("42".into())])
+                .unwrap()
+                .value,
+            Value::Natural(42)
+        );
+        assert_eq!(
+            call("parse", vec![Value::String("2.5".into())])
+                .unwrap()
+                .value,
+            Value::Decimal(2.5)
+        );
+        assert!(call("parse", vec![Value::String("forty two".into())]).is_err());
+        assert!(call("parse", vec![Value::String("".into())]).is_err());
+    }
+
+    fn naturals(values: &[i64]) -> Value {
+        Value::List(values.iter().copied().map(Value::Natural).collect())
+    }
+
+    #[test]
+    fn test_flatten() {
+        let nested = Value::List(vec![
+            naturals(&[1, 2]),
+            naturals(&[3]),
+            Value::Natural(4),
+            Value::List(vec![naturals(&[5])]),
+        ]);
+        assert_eq!(
+            call("flatten", vec![nested]).unwrap().value,
+            Value::List(vec![
+                Value::Natural(1),
+                Value::Natural(2),
+                Value::Natural(3),
+                Value::Natural(4),
+                naturals(&[5])
+            ])
+        );
+    }
+
+    #[test]
+    fn test_zip() {
+        let letters = Value::List(vec![Value::String("a".into()), Value::String("b".into())]);
+        assert_eq!(
+            call("zip", vec![naturals(&[1, 2, 3]), letters])
+                .unwrap()
+                .value,
+            Value::List(vec![
+                Value::List(vec![Value::Natural(1), Value::String("a".into())]),
+                Value::List(vec![Value::Natural(2), Value::String("b".into())]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_enumerate() {
+        let letters = Value::List(vec![Value::String("a".into()), Value::String("b".into())]);
+        assert_eq!(
+            call("enumerate", vec![letters]).unwrap().value,
+            Value::List(vec![
+                Value::List(vec![Value::Natural(0), Value::String("a".into())]),
+                Value::List(vec![Value::Natural(1), Value::String("b".into())]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_zip_with() {
+        let context = &mut Context::empty();
+        let mut eval = |input| crate::eval(input, context).unwrap().value;
+        eval("add(a, b) := a + b");
+        let sums = eval("zip_with(add, [1, 2, 3], [10, 20])").list().unwrap();
+        let sums: Vec<_> = sums.into_iter().map(|v| v.natural().unwrap()).collect();
+        assert_eq!(sums, vec![11, 22]);
+        assert_eq!(eval("zip_with(add, [], [1])"), naturals(&[]));
+        assert_eq!(
+            eval("zip_with(add, [\"a\"], [\"b\"])"),
+            Value::List(vec![Value::String("ab".into())])
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        let context = &mut Context::empty();
+        let mut eval = |input| crate::eval(input, context).unwrap().value;
+        eval("parity(x) := band(x, 1)");
+        assert_eq!(
+            eval("group_by(parity, [1, 2, 3, 4, 5])"),
+            Value::Map(vec![
+                (Value::Natural(1), naturals(&[1, 3, 5])),
+                (Value::Natural(0), naturals(&[2, 4])),
+            ])
+        );
+        assert_eq!(eval("group_by(parity, [])"), Value::Map(Vec::new()));
+    }
+
+    #[test]
+    fn test_take_drop() {
+        let list = naturals(&[1, 2, 3]);
+        let n = Value::Natural;
+        assert_eq!(
+            call("take", vec![list.clone(), n(2)]).unwrap().value,
+            naturals(&[1, 2])
+        );
+        assert_eq!(
+            call("take", vec![list.clone(), n(5)]).unwrap().value,
+            naturals(&[1, 2, 3])
+        );
+        assert_eq!(
+            call("drop", vec![list.clone(), n(2)]).unwrap().value,
+            naturals(&[3])
+        );
+        assert_eq!(
+            call("drop", vec![list.clone(), n(5)]).unwrap().value,
+            naturals(&[])
+        );
+        assert_eq!(call("drop", vec![list.clone(), n(-1)]).unwrap().value, list);
+    }
+
+    #[test]
+    fn test_take_drop_while() {
+        let context = &mut Context::empty();
+        let mut eval = |input| crate::eval(input, context).unwrap().value;
+        eval("small(x) := x < 3");
+        assert_eq!(eval("take_while(small, [1, 2, 5, 1])"), naturals(&[1, 2]));
+        assert_eq!(eval("drop_while(small, [1, 2, 5, 1])"), naturals(&[5, 1]));
+        assert_eq!(eval("take_while(small, [5, 1])"), naturals(&[]));
+        assert_eq!(eval("drop_while(small, [1, 2])"), naturals(&[]));
+        assert_eq!(eval("take_while(small, [])"), naturals(&[]));
+    }
+
+    #[test]
+    fn test_string_predicates() {
+        let test = |name, a: &str, b: &str| {
+            call(name, vec![Value::String(a.into()), Value::String(b.into())])
+                .unwrap()
+                .value
+                .bool()
+                .unwrap()
+        };
+        assert!(test("startswith", "evocation", "evo"));
+        assert!(!test("startswith", "evocation", "tion"));
+        assert!(test("startswith", "evocation", ""));
+        assert!(!test("startswith", "", "evo"));
+        assert!(test("endswith", "evocation", "tion"));
+        assert!(!test("endswith", "evocation", "evo"));
+        assert!(test("endswith", "", ""));
+        assert!(test("contains", "evocation", "oca"));
+        assert!(!test("contains", "evocation", "abj"));
+        assert!(test("contains", "evocation", ""));
+        assert!(call("contains", vec![naturals(&[1, 2]), Value::Natural(2)])
+            .unwrap()
+            .value
+            .bool()
+            .unwrap());
+    }
+
+    fn strings(values: &[&str]) -> Value {
+        Value::List(
+            values
+                .iter()
+                .map(|s| Value::String(s.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_split_join() {
+        let string = Value::String("fire,cold,acid".into());
+        let separator = Value::String(",".into());
+        let list = strings(&["fire", "cold", "acid"]);
+        assert_eq!(
+            call("split", vec![string.clone(), separator.clone()])
+                .unwrap()
+                .value,
+            list
+        );
+        assert_eq!(call("join", vec![list, separator]).unwrap().value, string);
+        assert_eq!(
+            call(
+                "split",
+                vec![Value::String("abc".into()), Value::String("".into())]
+            )
+            .unwrap()
+            .value,
+            strings(&["a", "b", "c"])
+        );
+        assert_eq!(
+            call("join", vec![naturals(&[1, 2]), Value::String("".into())])
+                .unwrap()
+                .value,
+            Value::String("12".into())
+        );
+    }
+
+    #[test]
+    fn test_case() {
+        let test = |name, s: &str| {
+            call(name, vec![Value::String(s.into())])
+                .unwrap()
+                .value
+                .string()
+                .unwrap()
+        };
+        assert_eq!(test("upper", "Fireball"), "FIREBALL");
+        assert_eq!(test("lower", "Fireball"), "fireball");
+        assert_eq!(test("upper", "straße"), "STRASSE");
+        assert_eq!(test("lower", "ÉCLAIR Ω"), "éclair ω");
+        assert_eq!(test("upper", ""), "");
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            call(
+                "format",
+                vec![
+                    Value::String("{} takes {} damage".into()),
+                    Value::String("Goblin".into()),
+                    Value::Natural(7)
+                ]
+            )
+            .unwrap()
+            .value,
+            Value::String("Goblin takes 7 damage".into())
+        );
+        assert_eq!(
+            call("format", vec![Value::String("plain".into())])
+                .unwrap()
+                .value,
+            Value::String("plain".into())
+        );
+        assert!(call(
+            "format",
+            vec![Value::String("{} and {}".into()), Value::Natural(1)]
+        )
+        .is_err());
+        assert!(call("format", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_print() {
+        assert_eq!(
+            call("print", vec![Value::Natural(3)]).unwrap(),
+            Outcome::empty()
+        );
+        assert_eq!(
+            call("fmt", vec![Value::Decimal(1.5)]).unwrap().value,
+            Value::String("1.5".into())
+        );
+        assert_eq!(
+            call("fmt", vec![Value::String("abc".into())])
+                .unwrap()
+                .value,
+            Value::String("abc".into())
+        );
+    }
+
+    #[test]
+    fn test_label() {
+        let outcome = call(
+            "label",
+            vec![Value::String("ac".into()), Value::Natural(15)],
+        )
+        .unwrap();
+        assert_eq!(outcome.label.as_deref(), Some("ac"));
+        assert_eq!(outcome.to_string(), "ac:\n15");
+
+        let context = &mut Context::empty();
+        let outcome = crate::eval("label(\"attack\", d20 + 5)", context).unwrap();
+        let roll = &outcome.rolls[0];
+        assert_eq!(
+            outcome.to_string(),
+            format!("attack:\n{roll}\n{}", roll.result + 5)
+        );
+
+        let outcome = crate::eval("label(\"damage\", 2d6)", context).unwrap();
+        assert_eq!(outcome.rolls.len(), 1);
+        assert!(outcome.to_string().starts_with("damage:\n2d6"));
+        assert!(call("label", vec![naturals(&[1]), Value::Natural(1)]).is_err());
+    }
+
+    #[test]
+    fn test_tag() {
+        let context = &mut Context::empty();
+        let mut eval = |input| crate::eval(input, context).unwrap().value;
+        let fire = eval("tag(8, \"fire\")");
+        assert_eq!(crate::value::format_value(&fire, 2), "8 (fire)");
+        assert_eq!(fire.source(), "tag(8, \"fire\")");
+        assert_eq!(Outcome::new(fire).format(2, false), "8 (fire)");
+        assert_eq!(eval("tag(8, \"fire\") + 2").to_string(), "10 (fire)");
+        assert_eq!(eval("2 * tag(8, \"fire\")").to_string(), "16 (fire)");
+        assert_eq!(
+            eval("tag(8, \"fire\") + tag(2, \"fire\")").to_string(),
+            "10 (fire)"
+        );
+        assert_eq!(
+            eval("tag(8, \"fire\") + tag(2, \"cold\")").to_string(),
+            "10"
+        );
+        assert_eq!(
+            eval("tag(tag(8, \"fire\"), \"cold\")").to_string(),
+            "8 (cold)"
+        );
+        assert_eq!(eval("tag(8, \"fire\") > 5"), Value::Bool(true));
+
+        let value = eval("tag(2d1, \"acid\")");
+        assert_eq!(value.tag(), Some("acid"));
+        assert_eq!(value.natural(), Ok(2));
+    }
+
+    #[test]
+    fn test_half() {
+        assert_eq!(call("half", vec![Value::Natural(7)]), Ok(Outcome::nat(3)));
+        assert_eq!(
+            call("resist", vec![Value::Decimal(9.5)]),
+            Ok(Outcome::nat(4))
+        );
+        assert_eq!(call("half", vec![Value::Natural(-3)]), Ok(Outcome::nat(-2)));
+        assert_eq!(
+            call("half", vec![Value::Decimal(1e300)]),
+            err("Numeric overflow.")
+        );
+        assert_eq!(
+            call("half", vec![Value::Natural(i64::MAX)]),
+            Ok(Outcome::nat(i64::MAX / 2))
+        );
+        assert_eq!(
+            call("half", vec![Value::Decimal(-2.5)]),
+            Ok(Outcome::nat(-2))
+        );
+
+        rng::seed(1);
+        let outcome = call("half", vec![Value::Roll(Roll::new(8, 6))]).unwrap();
+        assert_eq!(outcome.rolls.len(), 1);
+        assert_eq!(
+            outcome.value,
+            Value::Natural(outcome.rolls[0].result as i64 / 2)
+        );
+        rng::seed(1);
+        assert_eq!(
+            call("resist", vec![Value::Roll(Roll::new(8, 6))]).unwrap(),
+            outcome
+        );
+
+        let fire = Value::Natural(9).tagged("fire".into());
+        assert_eq!(
+            call("half", vec![fire]).unwrap().value.to_string(),
+            "4 (fire)"
+        );
+    }
+
+    #[test]
+    fn test_assert() {
+        assert!(call("assert", vec![Value::Bool(true)]).is_ok());
+        assert!(call("assert", vec![Value::Natural(1)]).is_ok());
+        assert_eq!(
+            call("assert", vec![Value::Bool(false)]),
+            Err("Assertion failed: false is false.".into())
+        );
+        assert!(call("assert_eq", vec![naturals(&[1]), naturals(&[1])]).is_ok());
+        assert_eq!(
+            call("assert_eq", vec![Value::Natural(1), Value::Natural(2)]),
+            Err("Assertion failed: 1 != 2.".into())
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        let context = &mut Context::empty();
+        assert_eq!(crate::eval("error(\"boom\")", context), err("boom"));
+        assert_eq!(crate::eval("error(3)", context), err("3"));
+        crate::eval("f(x) := if x < 0 then error(\"negative\") else x", context).unwrap();
+        assert_eq!(crate::eval("f(-1)", context), err("negative"));
+        crate::eval("g() := error(\"boom\")", context).unwrap();
+        assert_eq!(crate::eval("g", context), err("boom"));
+        assert_eq!(
+            crate::eval("try error(\"boom\") else 1", context).map(|o| o.value),
+            Ok(Value::Natural(1))
+        );
+    }
+
+    #[test]
+    fn test_sum() {
+        let context = &mut Context::empty();
+        let mut eval = |input| crate::eval(input, context).unwrap();
+
+        let outcome = eval("sum(repeat(d6, 3))");
+        assert_eq!(outcome.rolls.len(), 3);
+        let total: u64 = outcome.rolls.iter().map(|r| r.result).sum();
+        assert_eq!(outcome.value.natural(), Ok(total as i64));
+
+        assert_eq!(eval("sum([1, 2d1, 3])").value.natural(), Ok(6));
+        assert_eq!(eval("sum(4d1)").value.natural(), Ok(4));
+        assert_eq!(eval("sum([])").value, Value::Natural(0));
+        assert_eq!(eval("product([2, 3, 4])").value.natural(), Ok(24));
+        assert_eq!(eval("product(repeat(2d1, 3))").rolls.len(), 3);
+        assert!(crate::eval("repeat(1, -1)", &mut Context::empty()).is_err());
+        assert!(crate::eval("repeat(1, 10000000000000)", &mut Context::empty()).is_err());
+    }
+
+    #[test]
+    fn test_call_rolls() {
+        // Rolls made by functions a builtin calls are kept.
+        let context = &mut Context::empty();
+        crate::eval("f(x) := x + d4 > 3", context).unwrap();
+        let outcome = crate::eval("count([1, 2, 3], f)", context).unwrap();
+        assert_eq!(outcome.rolls.len(), 3);
+    }
+
+    #[test]
+    fn test_variance() {
+        let list = naturals(&[2, 4, 4, 4, 5, 5, 7, 9]);
+        assert_eq!(
+            call("variance", vec![list.clone()]).unwrap().value,
+            Value::Decimal(4.0)
+        );
+        assert_eq!(
+            call("stddev", vec![list]).unwrap().value,
+            Value::Decimal(2.0)
+        );
+        assert_eq!(
+            call("variance", vec![Value::List(vec![Value::Decimal(1.5)])])
+                .unwrap()
+                .value,
+            Value::Decimal(0.0)
+        );
+        assert!(call("stddev", vec![naturals(&[])]).is_err());
+    }
+
+    #[test]
+    fn test_combinatorics() {
+        let test = |name, args: &[i64]| {
+            call(name, args.iter().copied().map(Value::Natural).collect())
+                .map(|oc| oc.value.natural().unwrap())
+        };
+        assert_eq!(test("factorial", &[0]), Ok(1));
+        assert_eq!(test("factorial", &[5]), Ok(120));
+        assert_eq!(test("factorial", &[20]), Ok(2432902008176640000));
+        assert!(test("factorial", &[21]).is_err());
+        assert!(test("factorial", &[-1]).is_err());
+        assert_eq!(test("combinations", &[5, 2]), Ok(10));
+        assert_eq!(test("combinations", &[5, 6]), Ok(0));
+        assert_eq!(test("combinations", &[62, 31]), Ok(465428353255261088));
+        assert!(test("combinations", &[100, 50]).is_err());
+        assert_eq!(test("permutations", &[5, 2]), Ok(20));
+        assert_eq!(test("permutations", &[5, 0]), Ok(1));
+        assert!(test("permutations", &[100, 50]).is_err());
+        assert!(test("permutations", &[-1, 2]).is_err());
+    }
+
+    #[test]
+    fn test_bitwise() {
+        let test = |name, a, b| {
+            call(name, vec![Value::Natural(a), Value::Natural(b)])
+                .map(|oc| oc.value.natural().unwrap())
+        };
+        assert_eq!(test("band", 0b1100, 0b1010), Ok(0b1000));
+        assert_eq!(test("bor", 0b1100, 0b1010), Ok(0b1110));
+        assert_eq!(test("bxor", 0b1100, 0b1010), Ok(0b0110));
+        assert_eq!(test("shl", 3, 2), Ok(12));
+        assert_eq!(test("shr", 12, 2), Ok(3));
+        assert_eq!(test("shr", -8, 1), Ok(-4));
+        assert_eq!(test("shl", 1, 64), err("Cannot shift by 64 places."));
+        assert_eq!(test("shr", 1, -1), err("Cannot shift by -1 places."));
+        assert!(call("band", vec![Value::Natural(1), strings(&["a"])]).is_err());
+    }
+
+    #[test]
+    fn test_all_any() {
+        let test = |name, values: Vec<Value>| {
+            call(name, vec![Value::List(values)])
+                .unwrap()
+                .value
+                .bool()
+                .unwrap()
+        };
+        let mixed = vec![Value::Bool(true), Value::Natural(0), Value::Natural(2)];
+        let truthy = vec![Value::Bool(true), Value::Natural(1)];
+        assert!(!test("all", mixed.clone()));
+        assert!(test("any", mixed));
+        assert!(test("all", truthy));
+        assert!(!test("any", vec![Value::Bool(false), Value::Natural(0)]));
+        assert!(test("all", vec![]));
+        assert!(!test("any", vec![]));
+    }
+
+    #[test]
+    fn test_sort() {
+        assert_eq!(
+            call("sort", vec![naturals(&[3, 1, 2])]).unwrap().value,
+            naturals(&[1, 2, 3])
+        );
+        assert_eq!(
+            call("sort", vec![strings(&["b", "c", "a"])]).unwrap().value,
+            strings(&["a", "b", "c"])
+        );
+        assert!(call(
+            "sort",
+            vec![Value::List(vec![
+                Value::Natural(1),
+                Value::String("a".into())
+            ])]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_sort_desc() {
+        assert_eq!(
+            call("sort_desc", vec![naturals(&[3, 1, 2])]).unwrap().value,
+            naturals(&[3, 2, 1])
+        );
+
+        // Rolls sorted or shuffled are shown.
+        for name in ["sort", "sort_desc", "shuffle"] {
+            let outcome = call(name, vec![Value::Roll(Roll::new(4, 6))]).unwrap();
+            assert_eq!(outcome.rolls.len(), 1);
+            assert_eq!(outcome.value.list().unwrap().len(), 4);
+        }
+    }
+
+    #[test]
+    fn test_sort_by() {
+        let context = &mut Context::empty();
+        crate::eval("letter(c) := true", context).unwrap();
+        crate::eval("length(s) := count(split(s, \"\"), letter)", context).unwrap();
+        assert_eq!(
+            super::call(
+                context,
+                Context::GLOBAL_SCOPE,
+                "sort_by",
+                vec![
+                    strings(&["fireball", "bless", "wish", "haste"]),
+                    Value::Function("length".into())
+                ]
+            )
+            .unwrap()
+            .value,
+            strings(&["wish", "bless", "haste", "fireball"])
+        );
+        assert!(call("sort_by", vec![naturals(&[1]), Value::Natural(1)]).is_err());
+    }
+
+    #[test]
+    fn test_same_result() {
+        let context = &mut Context::empty();
+        let mut eval = |input| crate::eval(input, context).unwrap();
+
+        // == compares rolls before they are rolled, and numbers by type.
+        assert_eq!(eval("d6 == d6").value, Value::Bool(true));
+        assert_eq!(eval("d6 == d8").value, Value::Bool(false));
+        assert_eq!(eval("2 == 2.0").value, Value::Bool(false));
+
+        assert_eq!(eval("same_result(2, 2.0)").value, Value::Bool(true));
+        assert_eq!(eval("same_result(3d1, 3)").value, Value::Bool(true));
+        assert_eq!(eval("same_result(3d1, 2)").value, Value::Bool(false));
+        assert_eq!(
+            eval(r#"same_result([1, {"a": 2.0}], [1.0, {"a": 2}])"#).value,
+            Value::Bool(true)
+        );
+        assert_eq!(eval("same_result([1], [1, 2])").value, Value::Bool(false));
+        assert_eq!(eval(r#"same_result("1", 1)"#).value, Value::Bool(false));
+
+        rng::seed(1);
+        let outcome = eval("same_result(d20, d20)");
+        let [a, b] = [&outcome.rolls[0], &outcome.rolls[1]].map(|r| r.result);
+        assert_eq!(outcome.value, Value::Bool(a == b));
+    }
+
+    #[test]
+    fn test_keys_values() {
+        let map = Value::Map(vec![
+            (Value::String("str".into()), Value::Natural(16)),
+            (Value::String("dex".into()), Value::Natural(14)),
+            (Value::String("con".into()), Value::Natural(12)),
+        ]);
+        assert_eq!(
+            call("keys", vec![map.clone()]).unwrap().value,
+            strings(&["str", "dex", "con"])
+        );
+        assert_eq!(
+            call("values", vec![map]).unwrap().value,
+            naturals(&[16, 14, 12])
+        );
+        assert_eq!(
+            call("keys", vec![Value::Map(Vec::new())]).unwrap().value,
+            Value::List(Vec::new())
+        );
+        assert!(call("values", vec![naturals(&[1])]).is_err());
+    }
+
+    #[test]
+    fn test_min_max() {
+        assert_eq!(call("min", vec![naturals(&[3, 1, 2])]), Ok(Outcome::nat(1)));
+        assert_eq!(call("max", vec![naturals(&[3, 1, 2])]), Ok(Outcome::nat(3)));
+        assert_eq!(
+            call("max", vec![strings(&["b", "c", "a"])]).unwrap().value,
+            Value::String("c".into())
+        );
+        assert!(call("min", vec![naturals(&[])]).is_err());
+
+        rng::seed(1);
+        let outcome = call("min", vec![Value::Roll(Roll::new(4, 6))]).unwrap();
+        let dice = &outcome.rolls[0].rolls;
+        assert_eq!(dice.len(), 4);
+        assert_eq!(
+            outcome.value,
+            Value::Natural(*dice.iter().min().unwrap() as i64)
+        );
+
+        rng::seed(1);
+        let outcome = call("max", vec![Value::Roll(Roll::new(4, 6))]).unwrap();
+        assert_eq!(
+            outcome.value,
+            Value::Natural(*dice.iter().max().unwrap() as i64)
+        );
+    }
+
+    #[test]
+    fn test_count() {
+        assert_eq!(
+            call("count", vec![naturals(&[1, 2, 1, 3]), Value::Natural(1)]),
+            Ok(Outcome::nat(2))
+        );
+        assert_eq!(
+            call(
+                "count",
+                vec![strings(&["a", "b"]), Value::String("c".into())]
+            ),
+            Ok(Outcome::nat(0))
+        );
+
+        rng::seed(1);
+        let outcome = call(
+            "count",
+            vec![Value::Roll(Roll::new(6, 6)), Value::Natural(6)],
+        )
+        .unwrap();
+        assert_eq!(outcome.rolls.len(), 1);
+        let sixes = outcome.rolls[0].rolls.iter().filter(|&&d| d == 6).count();
+        assert_eq!(outcome.value, Value::Natural(sixes as i64));
+
+        let context = &mut Context::empty();
+        crate::eval("big(x) := x > 3", context).unwrap();
+        assert_eq!(
+            crate::eval("count([1, 5, 6, 2], big)", context)
+                .unwrap()
+                .value,
+            Value::Natural(2)
+        );
+    }
+
+    #[test]
+    fn test_reroll_if() {
+        let context = &mut Context::empty();
+        crate::eval("even(x) := contains([2, 4, 6], x)", context).unwrap();
+        rng::seed(1);
+        let outcome = crate::eval("reroll_if(8d6, even)", context).unwrap();
+
+        rng::seed(1);
+        let mut expected: Vec<u64> = (0..8).map(|_| rng::roll(6)).collect();
+        assert_eq!(outcome.rolls[0].rolls, expected);
+        let mut rerolls = Vec::new();
+        for d in expected.iter_mut() {
+            if *d % 2 == 0 {
+                *d = rng::roll(6);
+                rerolls.push(*d);
+            }
+        }
+        assert!(!rerolls.is_empty());
+        assert_eq!(outcome.rolls[1].rolls, rerolls);
+        assert_eq!(outcome.value, Value::Rolls(expected));
+
+        assert!(crate::eval("reroll_if(d20a, even)", context).is_err());
+        assert!(crate::eval("reroll_if([1, 2], even)", context).is_err());
+    }
+
+    #[test]
+    fn test_pool() {
+        let nat = |n: i64| Value::Natural(n);
+        rng::seed(1);
+        let outcome = call("pool", vec![nat(10), nat(10), nat(8)]).unwrap();
+
+        rng::seed(1);
+        let expected: Vec<u64> = (0..10).map(|_| rng::roll(10)).collect();
+        let successes = expected.iter().filter(|&&d| d >= 8).count() as i64;
+        let botches = expected.iter().filter(|&&d| d == 1).count() as i64;
+        let field = |name: &str| outcome.value.clone().get(Value::String(name.into()));
+        assert_eq!(field("successes"), Ok(nat(successes)));
+        assert_eq!(field("botches"), Ok(nat(botches)));
+        assert_eq!(field("rolls"), Ok(Value::Rolls(expected.clone())));
+        assert_eq!(outcome.rolls[0].rolls, expected);
+
+        assert!(call("pool", vec![nat(-1), nat(10), nat(8)]).is_err());
+        assert!(call("pool", vec![nat(3), nat(0), nat(8)]).is_err());
+    }
+
+    #[test]
+    fn test_clamp() {
+        let clamp = |value, lo, hi| {
+            call("clamp", vec![value, Value::Natural(lo), Value::Natural(hi)])
+                .map(|outcome| outcome.value)
+        };
+        assert_eq!(clamp(Value::Natural(7), 2, 5), Ok(Value::Natural(5)));
+        assert_eq!(clamp(Value::Decimal(1.5), 2, 5), Ok(Value::Natural(2)));
+        assert_eq!(clamp(Value::Natural(3), 2, 5), Ok(Value::Natural(3)));
+        assert_eq!(clamp(naturals(&[1, 3, 9]), 2, 5), Ok(naturals(&[2, 3, 5])));
+        assert!(clamp(Value::Natural(3), 5, 2).is_err());
+
+        let resolved = Value::Outcome(RollOutcome {
+            roll: Roll::new(5, 6),
+            rolls: vec![1, 2, 4, 6, 6],
+            result: 19,
+        });
+        assert_eq!(
+            clamp(resolved.clone(), 2, 5),
+            Ok(Value::Rolls(vec![2, 2, 4, 5, 5]))
+        );
+        assert!(clamp(resolved, -3, -1).is_err());
+
+        rng::seed(1);
+        let outcome = call(
+            "clamp",
+            vec![
+                Value::Roll(Roll::new(8, 6)),
+                Value::Natural(3),
+                Value::Natural(4),
+            ],
+        )
+        .unwrap();
+        let dice = outcome.value.rolls().unwrap();
+        assert_eq!(dice.len(), 8);
+        assert!(dice.iter().all(|&d| (3..=4).contains(&d)));
+        assert_eq!(outcome.rolls.len(), 1);
+    }
+
+    #[test]
+    fn test_choose() {
+        let list: Vec<Value> = (0..100).map(Value::Natural).collect();
+        rng::seed(1);
+        let first = call("choose", vec![Value::List(list.clone())]).unwrap();
+        rng::seed(1);
+        let second = call("choose", vec![Value::List(list.clone())]).unwrap();
+        assert_eq!(first, second);
+        assert!(list.contains(&first.value));
+        assert!(call("choose", vec![Value::List(Vec::new())]).is_err());
+    }
+
+    #[test]
+    fn test_stats() {
+        rng::seed(1);
+        let outcome = call("stats", vec![]).unwrap();
+        let scores = outcome.value.clone().list().unwrap();
+        assert_eq!(scores.len(), 6);
+        assert_eq!(outcome.rolls.len(), 6);
+        for (score, roll) in scores.into_iter().zip(&outcome.rolls) {
+            let score = score.natural().unwrap();
+            assert!((3..=18).contains(&score));
+            let mut dice = roll.rolls.clone();
+            dice.sort();
+            assert_eq!(score, dice[1..].iter().sum::<u64>() as i64);
+        }
+
+        rng::seed(1);
+        assert_eq!(call("stats", vec![]).unwrap(), outcome);
+    }
+
+    #[test]
+    fn test_flip() {
+        rng::seed(1);
+        let first: Vec<Outcome> = (0..8).map(|_| call("flip", vec![]).unwrap()).collect();
+        rng::seed(1);
+        let second: Vec<Outcome> = (0..8).map(|_| call("flip", vec![]).unwrap()).collect();
+        assert_eq!(first, second);
+        assert!(call("flip", vec![Value::Natural(1)]).is_err());
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let list: Vec<Value> = (1..=5).map(Value::Natural).collect();
+        rng::seed(1);
+        let shuffled = call("shuffle", vec![Value::List(list.clone())])
+            .unwrap()
+            .value
+            .list()
+            .unwrap();
+        assert_eq!(shuffled, [2, 1, 3, 4, 5].map(Value::Natural).to_vec());
+        let mut sorted = shuffled.clone();
+        sorted.sort_by_key(|v| v.clone().natural().unwrap());
+        assert_eq!(sorted, list);
+    }
+
+    #[test]
+    fn test_weighted() {
+        let values = Value::List(vec![Value::Natural(1), Value::Natural(2)]);
+        let weights = Value::List(vec![Value::Natural(3), Value::Decimal(1.0)]);
+
+        rng::seed(1);
+        let mut ones = 0;
+        for _ in 0..10000 {
+            let outcome = call("weighted", vec![values.clone(), weights.clone()]).unwrap();
+            if outcome.value == Value::Natural(1) {
+                ones += 1;
+            }
+        }
+        assert!((7250..7750).contains(&ones));
+
+        let zeroes = Value::List(vec![Value::Natural(0), Value::Natural(0)]);
+        assert!(call("weighted", vec![values.clone(), zeroes]).is_err());
+        let huge = Value::List(vec![Value::Decimal(1e308), Value::Decimal(1e308)]);
+        assert!(call("weighted", vec![values.clone(), huge]).is_err());
+        let short = Value::List(vec![Value::Natural(1)]);
+        assert!(call("weighted", vec![values, short]).is_err());
     }
 
     #[test]
@@ -1,13 +1,44 @@
+use std::io::IsTerminal;
+
 use crate::{
+    ast::{Ast, Node},
     context::Context,
-    err,
+    err, eval,
+    initiative::Tracker,
     load::{self, SaveTarget},
-    value::Value,
+    operator::Operator,
+    outcome::Outcome,
+    parser, token,
+    transcript::Transcript,
+    value::{format_value, Value, MAX_PRECISION},
     AppState, Res, CACHE_TITLE,
 };
 
 type CommandHandler = &'static dyn Fn(&[String], &mut AppState) -> Res<()>;
-const COMMANDS: &[(&str, CommandHandler)] = &[("exit", &exit), ("save", &save), ("load", &load)];
+const COMMANDS: &[(&str, CommandHandler)] = &[
+    ("exit", &exit),
+    ("save", &save),
+    ("load", &load),
+    ("test", &test),
+    ("ast", &ast),
+    ("trace", &trace),
+    ("history", &history),
+    ("precision", &precision),
+    ("init", &init),
+    ("log", &log),
+    ("color", &color),
+    ("default_die", &default_die),
+    ("rename", &rename),
+];
+
+// Commands taking an expression, which may contain spaces and quotes, so are
+// passed the rest of the line as entered rather than split into arguments.
+type ExpressionHandler = &'static dyn Fn(&str, &mut AppState) -> Res<()>;
+const EXPRESSION_COMMANDS: &[(&str, ExpressionHandler)] =
+    &[("describe", &describe), ("alias", &alias)];
+
+// Variable in the cache holding a map of alias names to their expressions.
+const ALIASES_VAR: &str = "ALIASES";
 
 fn single_opt_arg(args: &[String]) -> Res<Option<&str>> {
     match args {
@@ -82,6 +113,317 @@ pub fn load(args: &[String], state: &mut AppState) -> Res<()> {
     Ok(())
 }
 
+fn run_tests(input: &str) -> Res<String> {
+    let statements = parser::parse_tome(token::tokenise(input)?)?;
+    let mut context = Context::default();
+    let mut summary = String::new();
+    let mut passed = 0;
+    for (i, statement) in statements.iter().enumerate() {
+        match eval::evaluate(statement, &mut context, Context::GLOBAL_SCOPE) {
+            Ok(_) => passed += 1,
+            Err(e) => summary.push_str(&format!("Statement {} failed: {e}\n", i + 1)),
+        }
+    }
+    summary.push_str(&format!(
+        "{passed} passed, {} failed.",
+        statements.len() - passed
+    ));
+    Ok(summary)
+}
+
+fn test(args: &[String], _: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: test path";
+
+    let [path] = args else {
+        return err(USAGE);
+    };
+    match std::fs::read_to_string(path) {
+        Ok(input) => {
+            println!("{}", run_tests(&input)?);
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to read {path}: {e}")),
+    }
+}
+
+fn ast(args: &[String], _: &mut AppState) -> Res<()> {
+    if args.is_empty() {
+        return err("Usage: ast expression");
+    }
+
+    println!("{}", crate::parse(&args.join(" "))?.debug_tree());
+    Ok(())
+}
+
+/// Describe the outcome of an expression in words: each roll made, then each
+/// constant added, subtracted, multiplied or divided by at the top level of
+/// the expression, then the result.
+fn description(ast: &Ast, outcome: &Outcome, precision: usize) -> String {
+    let mut clauses: Vec<String> = outcome
+        .rolls
+        .iter()
+        .map(|roll| {
+            let faces: Vec<String> = roll.rolls.iter().map(u64::to_string).collect();
+            format!(
+                "rolled {}: [{}] = {}",
+                roll.roll,
+                faces.join(", "),
+                roll.result
+            )
+        })
+        .collect();
+
+    let constant = |id| match ast.get(id) {
+        Some(Node::Value(value @ (Value::Natural(_) | Value::Decimal(_)))) => {
+            Some(format_value(value, precision))
+        }
+        _ => None,
+    };
+
+    let mut steps = Vec::new();
+    let mut node = ast.start();
+    while let Some(&Node::Binary(lhs, op, rhs)) = ast.get(node)
+        && let Some(operand) = constant(rhs)
+    {
+        let step = match op {
+            Operator::Add => format!("added {operand}"),
+            Operator::Sub => format!("subtracted {operand}"),
+            Operator::Mul => format!("multiplied by {operand}"),
+            Operator::Div => format!("divided by {operand}"),
+            _ => break,
+        };
+        steps.push(step);
+        node = lhs;
+    }
+    let value = format_value(&outcome.value, precision);
+    let mut description = if !steps.is_empty() {
+        if let Some(start) = constant(node) {
+            clauses.push(format!("started with {start}"));
+        }
+        clauses.extend(steps.into_iter().rev());
+        format!("{} = {value}", clauses.join(", "))
+    } else {
+        match &outcome.rolls[..] {
+            [] => return value,
+            [roll] if roll.result.to_string() == value => clauses.join(", "),
+            _ => format!("{}, total {value}", clauses.join(", ")),
+        }
+    };
+
+    // Capitalise the first clause.
+    description.replace_range(..1, &description[..1].to_uppercase());
+    description
+}
+
+fn describe(expression: &str, state: &mut AppState) -> Res<()> {
+    if expression.is_empty() {
+        return err("Usage: describe expression");
+    }
+
+    let ast = crate::parse(expression)?;
+    let outcome = crate::eval_ast(&ast, &mut state.context)?;
+    println!("{}", description(&ast, &outcome, state.context.precision()));
+    Ok(())
+}
+
+fn trace(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: trace on|off";
+
+    match single_opt_arg(args)? {
+        Some("on") => state.context.set_tracing(true),
+        Some("off") => state.context.set_tracing(false),
+        _ => return err(USAGE),
+    }
+    Ok(())
+}
+
+fn history(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: history [count]";
+
+    let count = match single_opt_arg(args)? {
+        Some(arg) => arg.parse::<usize>().map_err(|_| USAGE.to_string())?,
+        None => Context::HISTORY_LENGTH,
+    };
+
+    let history: Vec<_> = state.context.history().collect();
+    for roll in &history[history.len().saturating_sub(count)..] {
+        println!("{roll}");
+    }
+    Ok(())
+}
+
+fn precision(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: precision places";
+
+    match single_opt_arg(args)? {
+        Some(arg) => {
+            let places = arg.parse::<usize>().map_err(|_| USAGE.to_string())?;
+            if places > MAX_PRECISION {
+                return Err(format!(
+                    "Precision {places} is too high, at most {MAX_PRECISION} places can be displayed."
+                ));
+            }
+            state.context.set_precision(places);
+        }
+        None => println!("{}", state.context.precision()),
+    }
+    Ok(())
+}
+
+fn default_die(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: default_die sides";
+
+    match single_opt_arg(args)? {
+        Some(arg) => match arg.parse::<u64>() {
+            Ok(die) if die > 0 => state.context.set_default_die(die),
+            _ => return err(USAGE),
+        },
+        None => println!("d{}", state.context.default_die()),
+    }
+    Ok(())
+}
+
+fn rename(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: rename old new";
+
+    let [old, new] = args else {
+        return err(USAGE);
+    };
+    state.context.rename(Context::GLOBAL_SCOPE, old, new)
+}
+
+/// Roll d20 plus a modifier for each entrant, given as name or
+/// name=modifier, and order them by the results.
+fn roll_initiative(args: &[String], context: &mut Context) -> Res<Tracker> {
+    let mut entrants = Vec::new();
+    for arg in args {
+        let (name, modifier) = arg.split_once('=').unwrap_or((arg, "0"));
+        let ast = crate::parse(&format!("d20 + ({modifier})"))?;
+        let (outcome, initiative) =
+            eval::evaluate(&ast, context, Context::GLOBAL_SCOPE)?.natural()?;
+        context.record_rolls(&outcome.rolls);
+        entrants.push((name.to_string(), initiative));
+    }
+    Ok(Tracker::new(entrants))
+}
+
+fn init(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: init [next | name[=modifier] ...]";
+
+    match args {
+        [] => {}
+        [arg] if arg == "next" => {
+            let Some(tracker) = &mut state.initiative else {
+                return err("No initiative has been rolled.");
+            };
+            tracker.next();
+        }
+        _ if args.iter().any(|arg| arg.starts_with('=')) => return err(USAGE),
+        _ => state.initiative = Some(roll_initiative(args, &mut state.context)?),
+    }
+
+    match &state.initiative {
+        Some(tracker) => println!("{tracker}"),
+        None => return err(USAGE),
+    }
+    Ok(())
+}
+
+fn log(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: log path|off";
+
+    match single_opt_arg(args)? {
+        Some("off") => state.transcript = None,
+        Some(path) => {
+            let transcript = Transcript::open(path)?;
+            println!("Logging to {}", transcript.path());
+            state.transcript = Some(transcript);
+        }
+        None => match &state.transcript {
+            Some(transcript) => println!("Logging to {}", transcript.path()),
+            None => return err(USAGE),
+        },
+    }
+    Ok(())
+}
+
+fn color(args: &[String], state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: color on|off";
+
+    match single_opt_arg(args)? {
+        Some("on") => state.color = true,
+        Some("off") => state.color = false,
+        _ => return err(USAGE),
+    }
+    Ok(())
+}
+
+/// Expression of an alias saved in the cache.
+fn alias_expression(cache: &Context, name: &str) -> Option<String> {
+    let aliases = cache.get_global(ALIASES_VAR)?.clone();
+    aliases.get(Value::String(name.into())).ok()?.string().ok()
+}
+
+fn set_alias(cache: &mut Context, name: &str, expression: String) -> Res<()> {
+    let is_command = COMMANDS.iter().any(|&(command, _)| command == name)
+        || EXPRESSION_COMMANDS
+            .iter()
+            .any(|&(command, _)| command == name);
+    if is_command {
+        return Err(format!("{name} is already a command."));
+    } else if name.is_empty() || !name.chars().all(|c| c.is_alphabetic() || c == '_') {
+        return Err(format!("Invalid alias name: {name}"));
+    }
+    crate::parse(&expression)?;
+
+    let aliases = cache
+        .get_global(ALIASES_VAR)
+        .cloned()
+        .unwrap_or(Value::Map(Vec::new()))
+        .set(Value::String(name.into()), Value::String(expression))?;
+    cache.set_variable(Context::GLOBAL_SCOPE, ALIASES_VAR, aliases);
+    Ok(())
+}
+
+/// Evaluate the expression of an alias as though it had been entered.
+fn run_alias(expression: &str, state: &mut AppState) -> Res<()> {
+    let color = state.color && std::io::stdout().is_terminal();
+    let (output, result) = crate::interpret(expression, &mut state.context, color);
+    crate::record(state, expression, &output);
+    result
+}
+
+fn alias(args: &str, state: &mut AppState) -> Res<()> {
+    const USAGE: &str = "Usage: alias [name [expression]]";
+
+    match split_word(args) {
+        ("", _) => match state.cache.get_global(ALIASES_VAR) {
+            Some(Value::Map(entries)) => {
+                for (name, expression) in entries {
+                    if let (Value::String(name), Value::String(expression)) = (name, expression) {
+                        println!("{name}: {expression}");
+                    }
+                }
+            }
+            _ => return err(USAGE),
+        },
+        (name, "") => match alias_expression(&state.cache, name) {
+            Some(expression) => println!("{expression}"),
+            None => return Err(format!("No alias named {name}.")),
+        },
+        (name, expression) => set_alias(&mut state.cache, name, expression.to_string())?,
+    }
+    Ok(())
+}
+
+/// Split the first word from a line, returning it and the rest of the line.
+fn split_word(input: &str) -> (&str, &str) {
+    match input.trim().split_once(' ') {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (input.trim(), ""),
+    }
+}
+
 fn parse_command(input: &str) -> Res<(String, Vec<String>)> {
     enum ParseState {
         Dot,
@@ -101,7 +443,7 @@ fn parse_command(input: &str) -> Res<(String, Vec<String>)> {
                 _ => return err("Command syntax: .<command> argument argument"),
             },
             ParseState::Command => match char {
-                _ if char.is_alphabetic() => command.push(char),
+                _ if char.is_alphabetic() || char == '_' => command.push(char),
                 '"' => state = ParseState::Quote,
                 ' ' => state = ParseState::Argument,
                 _ => return Err(format!("Invalid character in command name: {char}")),
@@ -142,18 +484,127 @@ fn parse_command(input: &str) -> Res<(String, Vec<String>)> {
 }
 
 pub fn handle(input: &str, state: &mut AppState) -> Res<()> {
+    if let Some((command, rest)) = input.strip_prefix('.').map(split_word) {
+        for (name, func) in EXPRESSION_COMMANDS {
+            if *name == command {
+                return (func)(rest, state);
+            }
+        }
+    }
+
     let (command, args) = parse_command(input)?;
     for (name, func) in COMMANDS {
         if *name == command {
             return (func)(&args, state);
         }
     }
+
+    // An alias is evaluated afresh each time it is run, rolling again.
+    if let Some(expression) = alias_expression(&state.cache, &command) {
+        if !args.is_empty() {
+            return Err(format!("Alias {command} takes no arguments."));
+        }
+        return run_alias(&expression, state);
+    }
     Err(format!("Not a command: {command}"))
 }
 
 #[cfg(test)]
 mod test {
-    use crate::commands::parse_command;
+    use crate::{
+        commands::{
+            alias_expression, description, parse_command, roll_initiative, run_tests, set_alias,
+            split_word,
+        },
+        context::Context,
+        initiative::Tracker,
+        outcome::Outcome,
+        rng,
+        roll::{Roll, RollOutcome},
+        value::Value,
+    };
+
+    fn describe(input: &str, value: Value, rolls: &[(Roll, Vec<u64>)]) -> String {
+        let mut outcome = Outcome::new(value);
+        for (roll, faces) in rolls {
+            outcome.rolls.push(RollOutcome {
+                roll: roll.clone(),
+                rolls: faces.clone(),
+                result: faces.iter().sum(),
+            });
+        }
+        description(&crate::parse(input).unwrap(), &outcome, 2)
+    }
+
+    #[test]
+    fn test_description() {
+        let three_d6 = || (Roll::new(3, 6), vec![4, 2, 5]);
+        assert_eq!(
+            describe("3d6 + 3", Value::Decimal(14.0), &[three_d6()]),
+            "Rolled 3d6: [4, 2, 5] = 11, added 3 = 14"
+        );
+        assert_eq!(
+            describe("(3d6 - 1) * 2", Value::Decimal(20.0), &[three_d6()]),
+            "Rolled 3d6: [4, 2, 5] = 11, subtracted 1, multiplied by 2 = 20"
+        );
+        assert_eq!(
+            describe("3d6", Value::Natural(11), &[three_d6()]),
+            "Rolled 3d6: [4, 2, 5] = 11"
+        );
+        assert_eq!(
+            describe(
+                "3d6 + d4",
+                Value::Decimal(14.0),
+                &[three_d6(), (Roll::new(1, 4), vec![3])]
+            ),
+            "Rolled 3d6: [4, 2, 5] = 11, rolled d4: [3] = 3, total 14"
+        );
+        assert_eq!(
+            describe("1 + 2", Value::Decimal(3.0), &[]),
+            "Started with 1, added 2 = 3"
+        );
+        assert_eq!(describe("max([1, 2])", Value::Natural(2), &[]), "2");
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut cache = Context::empty();
+        set_alias(&mut cache, "atk", "d20 + 5".into()).unwrap();
+        let expression = alias_expression(&cache, "atk").unwrap();
+        assert_eq!(expression, "d20 + 5");
+
+        // Each invocation rolls again.
+        let mut context = Context::empty();
+        rng::seed(1);
+        let first = crate::eval(&expression, &mut context).unwrap();
+        let second = crate::eval(&expression, &mut context).unwrap();
+        rng::seed(1);
+        assert_eq!(first.value.natural(), Ok(rng::roll(20) as i64 + 5));
+        assert_eq!(second.value.natural(), Ok(rng::roll(20) as i64 + 5));
+
+        set_alias(&mut cache, "atk", "d20 + 6".into()).unwrap();
+        assert_eq!(alias_expression(&cache, "atk").unwrap(), "d20 + 6");
+        assert!(set_alias(&mut cache, "save", "1".into()).is_err());
+        assert!(set_alias(&mut cache, "describe", "1".into()).is_err());
+        assert!(set_alias(&mut cache, "a1", "1".into()).is_err());
+        assert!(set_alias(&mut cache, "bad", "1 +".into()).is_err());
+        assert!(alias_expression(&cache, "bad").is_none());
+
+        // The expression is taken as entered, including spaces in strings.
+        let (name, expression) = split_word("greet  str(\"a b\")");
+        set_alias(&mut cache, name, expression.into()).unwrap();
+        assert_eq!(alias_expression(&cache, "greet").unwrap(), "str(\"a b\")");
+    }
+
+    #[test]
+    fn test_split_word() {
+        assert_eq!(
+            split_word("describe str(\"x\")"),
+            ("describe", "str(\"x\")")
+        );
+        assert_eq!(split_word("describe  d20 + 5 "), ("describe", "d20 + 5"));
+        assert_eq!(split_word("describe"), ("describe", ""));
+    }
 
     #[test]
     fn test_parse_command() {
@@ -162,6 +613,10 @@ mod test {
             parse_command(".load name").unwrap(),
             ("load".into(), vec!["name".into()])
         );
+        assert_eq!(
+            parse_command(".default_die 6").unwrap(),
+            ("default_die".into(), vec!["6".into()])
+        );
         assert_eq!(
             parse_command(".set var \"quoted\"").unwrap(),
             ("set".into(), vec!["var".into(), "quoted".into()])
@@ -169,4 +624,30 @@ mod test {
         assert!(parse_command(".cmd \"unclosed quote").is_err());
         assert!(parse_command("cmd arg arg").is_err());
     }
+
+    #[test]
+    fn test_roll_initiative() {
+        let mut context = Context::empty();
+        let args = ["goblin=2", "fighter=-1", "wizard"].map(String::from);
+        rng::seed(1);
+        let tracker = roll_initiative(&args, &mut context).unwrap();
+
+        rng::seed(1);
+        let expected = Tracker::new(vec![
+            ("goblin".into(), rng::roll(20) as i64 + 2),
+            ("fighter".into(), rng::roll(20) as i64 - 1),
+            ("wizard".into(), rng::roll(20) as i64),
+        ]);
+        assert_eq!(tracker, expected);
+        assert_eq!(context.history().count(), 3);
+        assert!(roll_initiative(&["orc=+".into()], &mut context).is_err());
+    }
+
+    #[test]
+    fn test_run_tests() {
+        assert_eq!(
+            run_tests("a = 2\nassert(a == 2)\nassert_eq(a, 3)").unwrap(),
+            "Statement 3 failed: Assertion failed: 2 != 3.\n2 passed, 1 failed."
+        );
+    }
 }
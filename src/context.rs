@@ -1,27 +1,63 @@
-use std::{collections::HashMap, fmt::Display, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    rc::Rc,
+};
 
 use crate::{
     ast::Ast,
+    err,
     eval::{check_argument_count, evaluate},
     eval_tome,
     outcome::Outcome,
-    value::Value,
+    roll::RollOutcome,
+    value::{Value, DEFAULT_PRECISION},
     Res,
 };
 
 #[derive(Debug)]
 struct Function {
+    id: usize, // Definition order, used to save functions in order.
     name: String,
     body: Ast,
     parameters: Vec<String>,
+    // Results of previous calls keyed by arguments, for memoised functions.
+    cache: Option<RefCell<HashMap<String, Value>>>,
 }
 
 impl Function {
-    fn new<S: ToString>(name: S, body: Ast, parameters: Vec<String>) -> Self {
+    fn new<S: ToString>(
+        id: usize,
+        name: S,
+        body: Ast,
+        parameters: Vec<String>,
+        memo: bool,
+    ) -> Self {
         Self {
+            id,
             name: name.to_string(),
             body,
             parameters,
+            cache: memo.then(|| RefCell::new(HashMap::new())),
+        }
+    }
+
+    fn cache_key(&self, args: &[Value]) -> Option<String> {
+        self.cache.as_ref().map(|_| format!("{args:?}"))
+    }
+
+    fn cached(&self, key: &str) -> Option<Value> {
+        self.cache.as_ref()?.borrow().get(key).cloned()
+    }
+
+    /// Cache the outcome of a call to this function if it is memoised. Calls
+    /// which rolled dice are not cached, as their results are random.
+    fn memoise(&self, key: String, outcome: &Outcome) {
+        if let Some(cache) = &self.cache
+            && outcome.rolls.is_empty()
+        {
+            cache.borrow_mut().insert(key, outcome.value.clone());
         }
     }
 }
@@ -30,7 +66,8 @@ impl Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{}({}) = {}",
+            "{}{}({}) := {}",
+            if self.cache.is_some() { "memo " } else { "" },
             &self.name,
             self.parameters.join(", "),
             self.body.render()
@@ -49,6 +86,8 @@ enum ScopeObject {
 struct Scope {
     parent: usize,
     objects: HashMap<String, ScopeObject>,
+    comments: HashMap<String, String>, // Comments on definitions, from tomes.
+    module: bool,                      // Whether this is the scope of a module.
 }
 
 impl Scope {
@@ -56,21 +95,45 @@ impl Scope {
         Self {
             parent,
             objects: HashMap::new(),
+            comments: HashMap::new(),
+            module: false,
         }
     }
 }
 
+/// Record of evaluated nodes and their values, for debugging.
+#[derive(Debug, Default)]
+struct Trace {
+    depth: usize,
+    lines: Vec<String>,
+}
+
 #[derive(Debug)]
 pub struct Context {
     scopes: Vec<Scope>,
+    trace: Option<Trace>,
+    functions_defined: usize,
+    history: VecDeque<RollOutcome>,
+    precision: usize, // Decimal places to display.
+    depth: usize,     // Number of function calls in progress.
+    default_die: u64, // Die rolled when a roll doesn't specify one.
 }
 
 impl Context {
     pub const GLOBAL_SCOPE: usize = 0;
+    pub const HISTORY_LENGTH: usize = 100;
+    pub const MAX_DEPTH: usize = 128;
+    pub const DEFAULT_DIE: u64 = 20;
 
     fn new() -> Self {
         Self {
             scopes: vec![Scope::new(usize::MAX)],
+            trace: None,
+            functions_defined: 0,
+            history: VecDeque::new(),
+            precision: DEFAULT_PRECISION,
+            depth: 0,
+            default_die: Self::DEFAULT_DIE,
         }
     }
 
@@ -79,6 +142,12 @@ impl Context {
     }
 
     fn lookup(&self, scope: usize, name: &str) -> Option<&ScopeObject> {
+        // Members of a module are looked up only in the module's scope.
+        if let Some((path, member)) = name.rsplit_once('.') {
+            let module = self.module_scope(scope, path)?;
+            return self.scopes.get(module)?.objects.get(member);
+        }
+
         let scope = self.scopes.get(scope)?;
         scope
             .objects
@@ -86,13 +155,58 @@ impl Context {
             .or_else(|| self.lookup(scope.parent, name))
     }
 
-    fn child_scope(&mut self, parent: usize) -> usize {
+    /// Scope of the module at a path of dot separated module names.
+    fn module_scope(&self, scope: usize, path: &str) -> Option<usize> {
+        match self.lookup(scope, path)? {
+            &ScopeObject::Child(module) => Some(module),
+            _ => None,
+        }
+    }
+
+    /// Scope of a module defined in a scope, creating it if it doesn't exist.
+    pub fn module<S: ToString>(&mut self, scope: usize, name: S) -> Res<usize> {
+        // Function and block scopes are popped when they finish, so a module
+        // scope pushed while one is live would be popped in its place. This
+        // includes modules in tomes imported by a function or block.
+        if self.depth > 0 {
+            return err("Modules cannot be defined in functions.");
+        }
+        if self.scopes.iter().skip(1).any(|scope| !scope.module) {
+            return err("Modules cannot be defined in blocks.");
+        }
+
+        let name = name.to_string();
+        let Some(parent) = self.scopes.get(scope) else {
+            return err("Attempted to define module in scope which doesn't exist.");
+        };
+        match parent.objects.get(&name) {
+            Some(&ScopeObject::Child(module)) => Ok(module),
+            Some(_) => Err(format!("{name} is already defined and is not a module.")),
+            None => {
+                let module = self.child_scope(scope);
+                self.scopes[module].module = true;
+                self.scopes[scope]
+                    .objects
+                    .insert(name, ScopeObject::Child(module));
+                Ok(module)
+            }
+        }
+    }
+
+    /// Create a new scope as a child of another, returning its index. It
+    /// should be discarded with pop_scope once it is no longer needed.
+    pub fn child_scope(&mut self, parent: usize) -> usize {
         let scope = Scope::new(parent);
         let idx = self.scopes.len();
         self.scopes.push(scope);
         idx
     }
 
+    /// Discard the most recently created scope.
+    pub fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
     /// Return a vector listing the stack of scope indexes from a scope up to
     /// the global scope.
     fn scope_stack(&self, mut idx: usize) -> Vec<usize> {
@@ -116,6 +230,86 @@ impl Context {
         }
     }
 
+    pub fn set_tracing(&mut self, enabled: bool) {
+        self.trace = enabled.then(Trace::default);
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Note the start of the evaluation of a node, indenting lines traced
+    /// until it finishes.
+    pub fn trace_enter(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.depth += 1;
+        }
+    }
+
+    /// Note the end of the evaluation of a node, recording a line.
+    pub fn trace_exit(&mut self, line: String) {
+        if let Some(trace) = &mut self.trace {
+            trace.depth = trace.depth.saturating_sub(1);
+            trace
+                .lines
+                .push(format!("{}{line}", "  ".repeat(trace.depth)));
+        }
+    }
+
+    /// Take all lines traced so far, resetting indentation.
+    pub fn take_trace(&mut self) -> Vec<String> {
+        if let Some(trace) = &mut self.trace {
+            trace.depth = 0;
+            std::mem::take(&mut trace.lines)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Names of builtins and of functions and variables in the global scope,
+    /// sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = crate::builtins::names().map(String::from).collect();
+        if let Some(global) = self.scopes.get(Self::GLOBAL_SCOPE) {
+            names.extend(global.objects.keys().cloned());
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+
+    pub fn set_precision(&mut self, precision: usize) {
+        self.precision = precision;
+    }
+
+    pub fn default_die(&self) -> u64 {
+        self.default_die
+    }
+
+    pub fn set_default_die(&mut self, die: u64) {
+        self.default_die = die;
+    }
+
+    /// Add rolls to the history, discarding the oldest rolls beyond
+    /// HISTORY_LENGTH.
+    pub fn record_rolls(&mut self, rolls: &[RollOutcome]) {
+        for roll in rolls {
+            if self.history.len() == Self::HISTORY_LENGTH {
+                self.history.pop_front();
+            }
+            self.history.push_back(roll.clone());
+        }
+    }
+
+    /// Rolls made in this session, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &RollOutcome> {
+        self.history.iter()
+    }
+
     pub fn get_global(&self, name: &str) -> Option<&Value> {
         self.get_variable(Self::GLOBAL_SCOPE, name)
     }
@@ -123,7 +317,13 @@ impl Context {
     pub fn set_variable<S: ToString>(&mut self, scope: usize, name: S, value: Value) {
         let name = name.to_string();
         let mut set_scope = scope;
-        for idx in self.scope_stack(scope) {
+        // Assignments in the body of a module define members of it, rather
+        // than assigning to variables outside of it.
+        let stack = match self.scopes.get(scope) {
+            Some(module) if module.module => Vec::new(),
+            _ => self.scope_stack(scope),
+        };
+        for idx in stack {
             if let Some(scope) = self.scopes.get_mut(idx) {
                 if scope.objects.contains_key(&name) {
                     set_scope = idx;
@@ -139,6 +339,16 @@ impl Context {
             .insert(name.to_string(), ScopeObject::Value(value));
     }
 
+    /// Bind a variable in the given scope, shadowing any variable of the same
+    /// name in a parent scope.
+    fn bind_variable<S: ToString>(&mut self, scope: usize, name: S, value: Value) {
+        self.scopes
+            .get_mut(scope)
+            .expect("Attempted to bind variable in scope which doesn't exist.")
+            .objects
+            .insert(name.to_string(), ScopeObject::Value(value));
+    }
+
     fn get_function(&self, scope: usize, name: &str) -> Option<Rc<Function>> {
         if let ScopeObject::Function(func) = self.lookup(scope, name)? {
             Some(func.clone())
@@ -153,8 +363,16 @@ impl Context {
         name: S,
         body: Ast,
         parameters: Vec<String>,
+        memo: bool,
     ) {
-        let function = Function::new(name.to_string(), body, parameters);
+        let function = Function::new(
+            self.functions_defined,
+            name.to_string(),
+            body,
+            parameters,
+            memo,
+        );
+        self.functions_defined += 1;
         self.scopes
             .get_mut(scope)
             .expect("Attempted to define function in scope that doesn't exist.")
@@ -162,39 +380,163 @@ impl Context {
             .insert(name.to_string(), ScopeObject::Function(Rc::new(function)));
     }
 
+    /// Move the variable, function or module defined as old in a scope to new.
+    /// References to old elsewhere are not updated.
+    pub fn rename(&mut self, scope: usize, old: &str, new: &str) -> Res<()> {
+        let valid = new
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && new.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !valid {
+            return Err(format!("Invalid name: {new}"));
+        }
+
+        let Some(scope) = self.scopes.get_mut(scope) else {
+            return err("Attempted to rename in scope which doesn't exist.");
+        };
+        if scope.objects.contains_key(new) {
+            return Err(format!("{new} is already defined."));
+        }
+        let Some(mut object) = scope.objects.remove(old) else {
+            return Err(format!("{old} is not defined."));
+        };
+
+        if let ScopeObject::Function(func) = &mut object
+            && let Some(func) = Rc::get_mut(func)
+        {
+            func.name = new.to_string();
+        }
+        scope.objects.insert(new.to_string(), object);
+        if let Some(comment) = scope.comments.remove(old) {
+            scope.comments.insert(new.to_string(), comment);
+        }
+        Ok(())
+    }
+
     pub fn call(&mut self, scope: usize, name: &str, args: Vec<Value>) -> Res<Outcome> {
         if let Some(function) = self.get_function(scope, name) {
-            let func_scope = self.child_scope(scope);
             check_argument_count(name, function.parameters.len(), &args)?;
+            let key = function.cache_key(&args);
+            if let Some(value) = key.as_deref().and_then(|k| function.cached(k)) {
+                return Ok(Outcome::new(value));
+            }
+
+            if self.depth >= Self::MAX_DEPTH {
+                return err("Maximum recursion depth exceeded.");
+            }
+
+            // Functions in a module are called in its scope, so that they
+            // can refer to other members of the module by name.
+            let parent = name
+                .rsplit_once('.')
+                .and_then(|(path, _)| self.module_scope(scope, path))
+                .unwrap_or(scope);
+            let func_scope = self.child_scope(parent);
             for (name, value) in function.parameters.iter().zip(args) {
-                self.set_variable(func_scope, name, value);
+                self.bind_variable(func_scope, name, value);
             }
+            self.depth += 1;
             let ret = evaluate(&function.body, self, func_scope);
-            self.scopes.pop();
+            self.depth -= 1;
+            self.pop_scope();
+
+            if let (Some(key), Ok(outcome)) = (key, &ret) {
+                function.memoise(key, outcome);
+            }
             ret
+        } else if let Some(Value::Function(target)) = self.get_variable(scope, name)
+            && target != name
+        {
+            let target = target.clone();
+            self.call(scope, &target, args)
         } else {
-            crate::builtins::call(name, args)
+            crate::builtins::call(self, scope, name, args)
+        }
+    }
+
+    /// Whether the name refers to a function or builtin.
+    pub fn is_function(&self, scope: usize, name: &str) -> bool {
+        self.get_function(scope, name).is_some() || crate::builtins::names().any(|n| n == name)
+    }
+
+    /// Whether the name refers to a function which takes arguments, so that
+    /// it is used as a value rather than called when referenced by name.
+    pub fn takes_arguments(&self, scope: usize, name: &str) -> bool {
+        match self.get_function(scope, name) {
+            Some(function) => !function.parameters.is_empty(),
+            None => crate::builtins::takes_arguments(name),
+        }
+    }
+
+    /// Attach a comment to a name defined in a scope, to be saved with it.
+    pub fn set_comment<S: ToString>(&mut self, scope: usize, name: S, comment: &str) {
+        if let Some(scope) = self.scopes.get_mut(scope) {
+            scope.comments.insert(name.to_string(), comment.to_string());
+        }
+    }
+
+    /// Definitions which recreate the contents of a scope, each with its
+    /// comment. Functions are emitted in definition order, so that
+    /// redefinitions replace earlier definitions, followed by modules and then
+    /// variables, each sorted by name.
+    fn definitions<'a>(&'a self, scope: &'a Scope) -> Vec<(String, Option<&'a String>)> {
+        let mut functions = Vec::new();
+        let mut modules = Vec::new();
+        let mut variables = Vec::new();
+        for (name, object) in &scope.objects {
+            match object {
+                ScopeObject::Function(func) => functions.push(func),
+                ScopeObject::Value(Value::Empty) => {}
+                ScopeObject::Value(value) => variables.push((name, value)),
+                &ScopeObject::Child(module) => modules.push((name, module)),
+            }
+        }
+        functions.sort_by_key(|func| func.id);
+        modules.sort_by_key(|&(name, _)| name);
+        variables.sort_by_key(|&(name, _)| name);
+
+        let mut definitions = Vec::new();
+        for func in functions {
+            definitions.push((func.to_string(), scope.comments.get(&func.name)));
+        }
+        for (name, module) in modules {
+            let Some(module) = self.scopes.get(module) else {
+                continue;
+            };
+
+            let mut body = String::new();
+            for (definition, comment) in self.definitions(module) {
+                body += &format!("    {}", definition.replace('\n', "\n    "));
+                if let Some(comment) = comment {
+                    body += &format!(" # {comment}");
+                }
+                body.push('\n');
+            }
+            definitions.push((format!("module {name} {{\n{body}}}"), None));
         }
+        for (name, value) in variables {
+            definitions.push((
+                format!("{name} = {}", value.source()),
+                scope.comments.get(name),
+            ));
+        }
+        definitions
     }
 
+    /// Render the global scope as a tome which recreates it when evaluated.
     pub fn dump_to_string(&self) -> Res<String> {
-        let mut ret = String::new();
+        let Some(global) = self.scopes.get(Self::GLOBAL_SCOPE) else {
+            return err("No scope available to dump to string.");
+        };
 
-        // TODO establish module syntax.
-        // let Some(user_scope) = self.scope.last() else {
-        //     return err("No scope available to dump to string.");
-        // };
-
-        // // Sort functions by definition order.
-        // let mut functions: Vec<&Rc<Function>> = user_scope.functions.values().collect();
-        // functions.sort_by(|a, b| (a.id).cmp(&b.id));
-        // for func in functions {
-        //     ret += &format!("{func}\n");
-        // }
-
-        // for (k, v) in &user_scope.variables {
-        //     ret += &format!("{k} = {v}\n");
-        // }
+        let mut ret = String::new();
+        for (definition, comment) in self.definitions(global) {
+            match comment {
+                Some(comment) => ret += &format!("{definition} # {comment}\n"),
+                None => ret += &format!("{definition}\n"),
+            }
+        }
         Ok(ret)
     }
 }
@@ -222,6 +564,293 @@ mod test {
         assert_eq!(func.parameters, vec!["x".to_string(), "y".to_string()]);
     }
 
+    #[test]
+    fn test_dump_to_string() {
+        let mut context = Context::empty();
+        eval("g(x) := x * 2", &mut context).unwrap();
+        eval("f() := g(b) + a", &mut context).unwrap();
+        eval("memo h(x) := (x + 1) * 2", &mut context).unwrap();
+        eval("b = 3", &mut context).unwrap();
+        eval("a = \"s\"", &mut context).unwrap();
+        let dump = context.dump_to_string().unwrap();
+        assert_eq!(
+            dump,
+            "g(x) := x * 2\nf() := g(b) + a\nmemo h(x) := (x + 1) * 2\na = \"s\"\nb = 3\n"
+        );
+
+        let mut loaded = Context::empty();
+        eval_tome(&dump, &mut loaded).unwrap();
+        assert_eq!(
+            eval("f()", &mut loaded).unwrap().value,
+            Value::String("6s".into())
+        );
+        assert_eq!(loaded.dump_to_string().unwrap(), dump);
+    }
+
+    #[test]
+    fn test_dump_comments() {
+        let tome = "# Unattached comments are dropped.\nattack(x) := d20 + x # To hit.\nac = 15 #Armour.\nhp = 10\n";
+        let mut context = Context::empty();
+        eval_tome(tome, &mut context).unwrap();
+        let dump = context.dump_to_string().unwrap();
+        assert_eq!(
+            dump,
+            "attack(x) := d20 + x # To hit.\nac = 15 # Armour.\nhp = 10\n"
+        );
+
+        // Redefinitions without a comment keep the existing comment.
+        eval("ac = 16", &mut context).unwrap();
+        assert!(context
+            .dump_to_string()
+            .unwrap()
+            .contains("ac = 16 # Armour.\n"));
+
+        let mut loaded = Context::empty();
+        eval_tome(&dump, &mut loaded).unwrap();
+        assert_eq!(loaded.dump_to_string().unwrap(), dump);
+    }
+
+    #[test]
+    fn test_module() {
+        let mut context = Context::empty();
+        eval("bonus = 2", &mut context).unwrap();
+        eval(
+            "module combat { atk(m) := 20 + hit(m) hit(m) := m + bonus }",
+            &mut context,
+        )
+        .unwrap();
+        assert_eq!(
+            eval("combat.atk(5)", &mut context).unwrap().value.natural(),
+            Ok(27)
+        );
+        assert!(eval("atk(5)", &mut context).is_err());
+        assert!(eval("combat.bonus", &mut context).is_err());
+
+        // Modules may be reopened and nested.
+        eval("module combat { module spells { dc = 13 } }", &mut context).unwrap();
+        assert_eq!(
+            eval("combat.spells.dc", &mut context)
+                .unwrap()
+                .value
+                .natural(),
+            Ok(13)
+        );
+        assert_eq!(
+            eval("sort_by([2, 1], combat.hit)", &mut context)
+                .unwrap()
+                .value,
+            eval("[1, 2]", &mut context).unwrap().value
+        );
+
+        assert!(eval("combat.x = 1", &mut context).is_err());
+        assert!(eval("module bonus { x = 1 }", &mut context).is_err());
+        eval("f() := module m { x = 1 }", &mut context).unwrap();
+        assert!(eval("f()", &mut context).is_err());
+    }
+
+    #[test]
+    fn test_module_in_temporary_scope() {
+        let path = std::env::temp_dir().join("spells_test_module_import.tome");
+        std::fs::write(&path, "module m { a = 1 }").unwrap();
+        let import = format!("import \"{}\"", path.display());
+
+        let mut context = Context::empty();
+        assert!(eval(&format!("f() := {import}"), &mut context).is_ok());
+        assert!(eval("f()", &mut context).is_err());
+        assert!(eval(&format!("{{ {import} }}"), &mut context).is_err());
+        assert!(eval("{ module m { a = 1 } }", &mut context).is_err());
+        assert!(eval("m.a", &mut context).is_err());
+
+        eval(&import, &mut context).unwrap();
+        eval("g(a) := m.a", &mut context).unwrap();
+        assert_eq!(eval("g(7)", &mut context).unwrap().value.natural(), Ok(1));
+        assert_eq!(eval("m.a", &mut context).unwrap().value.natural(), Ok(1));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_module_collisions() {
+        let mut context = Context::empty();
+        eval("x = 0", &mut context).unwrap();
+        eval("f() := 0", &mut context).unwrap();
+        eval("module a { f() := 1 x = 1 }", &mut context).unwrap();
+        eval("module b { x = 2 f() := 2 }", &mut context).unwrap();
+        let value =
+            |expr: &str, context: &mut Context| eval(expr, context).unwrap().value.natural();
+        assert_eq!(value("f() + x", &mut context), Ok(0));
+        assert_eq!(value("a.f() + a.x", &mut context), Ok(2));
+        assert_eq!(value("b.f() + b.x", &mut context), Ok(4));
+
+        // Within a module its own members shadow global names.
+        eval("module a { g() := f() * 10 }", &mut context).unwrap();
+        assert_eq!(value("a.g()", &mut context), Ok(10));
+    }
+
+    #[test]
+    fn test_dump_modules() {
+        let mut context = Context::empty();
+        eval(
+            "module m { f(x) := x + 1 module n { y = 2 } }",
+            &mut context,
+        )
+        .unwrap();
+        eval("z = m.f(m.n.y)", &mut context).unwrap();
+        let dump = context.dump_to_string().unwrap();
+        assert_eq!(
+            dump,
+            "module m {\n    f(x) := x + 1\n    module n {\n        y = 2\n    }\n}\nz = 3\n"
+        );
+
+        let mut loaded = Context::empty();
+        eval_tome(&dump, &mut loaded).unwrap();
+        assert_eq!(loaded.dump_to_string().unwrap(), dump);
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut context = Context::empty();
+        eval("hp = 12", &mut context).unwrap();
+        context
+            .rename(Context::GLOBAL_SCOPE, "hp", "health")
+            .unwrap();
+        assert!(context.get_global("hp").is_none());
+        assert_eq!(context.get_global("health"), Some(&Value::Natural(12)));
+
+        eval("atk(m) := 10 + m", &mut context).unwrap();
+        context
+            .rename(Context::GLOBAL_SCOPE, "atk", "attack")
+            .unwrap();
+        assert!(eval("atk(1)", &mut context).is_err());
+        assert_eq!(
+            eval("attack(1)", &mut context).unwrap().value.natural(),
+            Ok(11)
+        );
+        assert_eq!(
+            context.dump_to_string().unwrap(),
+            "attack(m) := 10 + m\nhealth = 12\n"
+        );
+
+        let rename =
+            |context: &mut Context, old, new| context.rename(Context::GLOBAL_SCOPE, old, new);
+        assert!(rename(&mut context, "missing", "x").is_err());
+        assert!(rename(&mut context, "health", "attack").is_err());
+        assert!(rename(&mut context, "health", "1hp").is_err());
+        assert!(rename(&mut context, "health", "a.b").is_err());
+        assert_eq!(context.get_global("health"), Some(&Value::Natural(12)));
+    }
+
+    #[test]
+    fn test_history() {
+        let mut context = Context::empty();
+        eval("d20 + 2d6", &mut context).unwrap();
+        eval("3 + 4", &mut context).unwrap();
+        eval("d4", &mut context).unwrap();
+        let history: Vec<&RollOutcome> = context.history().collect();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].roll.die, 20);
+        assert_eq!(history[1].roll.die, 6);
+        assert_eq!(history[1].rolls.len(), 2);
+        assert_eq!(history[2].roll.die, 4);
+
+        for _ in 0..Context::HISTORY_LENGTH {
+            eval("d8", &mut context).unwrap();
+        }
+        assert_eq!(context.history().count(), Context::HISTORY_LENGTH);
+        assert!(context.history().all(|r| r.roll.die == 8));
+    }
+
+    #[test]
+    fn test_memoised_definition() {
+        let mut context = Context::empty();
+        eval("calls = 0", &mut context).unwrap();
+        eval("memo double(x) := calls = calls + 1; x * 2", &mut context).unwrap();
+        assert_eq!(
+            eval("double(2)", &mut context).unwrap().value.natural(),
+            Ok(4)
+        );
+        assert_eq!(
+            eval("double(2)", &mut context).unwrap().value.natural(),
+            Ok(4)
+        );
+        assert_eq!(
+            context.get_global("calls").cloned().unwrap().natural(),
+            Ok(1)
+        );
+        assert_eq!(
+            eval("double(3)", &mut context).unwrap().value.natural(),
+            Ok(6)
+        );
+        assert_eq!(
+            context.get_global("calls").cloned().unwrap().natural(),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_memoised_rolls_not_cached() {
+        let mut context = Context::empty();
+        eval("calls = 0", &mut context).unwrap();
+        eval("memo attack() := calls = calls + 1; d20 + 0", &mut context).unwrap();
+        eval("attack()", &mut context).unwrap();
+        eval("attack()", &mut context).unwrap();
+        assert_eq!(
+            context.get_global("calls").cloned().unwrap().natural(),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_recursion_limit() {
+        // Evaluation frames are large in debug builds, so recursing to the
+        // limit needs more than the default stack of a test thread.
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(recursion_limit)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn recursion_limit() {
+        let mut context = Context::empty();
+        eval("loop() := loop()", &mut context).unwrap();
+        assert_eq!(
+            eval("loop()", &mut context),
+            err("Maximum recursion depth exceeded.")
+        );
+        assert_eq!(context.depth, 0);
+        assert_eq!(context.scopes.len(), 1);
+
+        eval(
+            "count(n) := if n <= 0 then 0 else 1 + count(n - 1)",
+            &mut context,
+        )
+        .unwrap();
+        assert_eq!(
+            eval("count(100)", &mut context).unwrap().value.natural(),
+            Ok(100)
+        );
+    }
+
+    #[test]
+    fn test_global_assignment_in_function() {
+        let mut context = Context::empty();
+        eval("hp = 10", &mut context).unwrap();
+        eval("heal(n) := hp = hp + n", &mut context).unwrap();
+        eval("heal(5)", &mut context).unwrap();
+        assert_eq!(context.get_global("hp").cloned().unwrap().natural(), Ok(15));
+        assert_eq!(context.scopes.len(), 1);
+    }
+
+    #[test]
+    fn test_parameter_shadows_global() {
+        let mut context = Context::empty();
+        eval("x = 1", &mut context).unwrap();
+        eval("f(x) := x = x + 1", &mut context).unwrap();
+        assert_eq!(eval("f(5)", &mut context).unwrap().value.natural(), Ok(6));
+        assert_eq!(context.get_global("x"), Some(&Value::Natural(1)));
+    }
+
     #[test]
     fn test_global_assignment() {
         // Test that setting a variable that already exists in a parent scope
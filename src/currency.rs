@@ -0,0 +1,231 @@
+use crate::{
+    context::Context,
+    err,
+    value::{Value, OVERFLOW},
+    Res,
+};
+
+// Denominations and their values in copper pieces, ascending.
+const DENOMINATIONS: &[(&str, i64)] =
+    &[("cp", 1), ("sp", 10), ("ep", 50), ("gp", 100), ("pp", 1000)];
+
+const ELECTRUM: &str = "ep";
+const USE_ELECTRUM_VAR: &str = "_use_electrum";
+
+/// Coins held in each denomination in use, stored in the global variables
+/// used by the default tome, e.g. _gp.
+struct Purse {
+    coins: Vec<(&'static str, i64, i64)>, // (Denomination, value, count).
+}
+
+impl Purse {
+    fn variable(denomination: &str) -> String {
+        format!("_{denomination}")
+    }
+
+    fn load(context: &Context) -> Res<Self> {
+        let use_electrum = match context.get_global(USE_ELECTRUM_VAR) {
+            Some(value) => value.clone().bool()?,
+            None => false,
+        };
+
+        let mut coins = Vec::new();
+        for &(denomination, value) in DENOMINATIONS {
+            if denomination == ELECTRUM && !use_electrum {
+                continue;
+            }
+            let count = match context.get_global(&Self::variable(denomination)) {
+                Some(count) => count.clone().natural()?,
+                None => 0,
+            };
+            coins.push((denomination, value, count));
+        }
+        Ok(Self { coins })
+    }
+
+    fn store(&self, context: &mut Context) {
+        for &(denomination, _, count) in &self.coins {
+            context.set_variable(
+                Context::GLOBAL_SCOPE,
+                Self::variable(denomination),
+                Value::Natural(count),
+            );
+        }
+    }
+
+    fn index(&self, denomination: &str) -> Res<usize> {
+        match self.coins.iter().position(|&(d, _, _)| d == denomination) {
+            Some(index) => Ok(index),
+            None => Err(format!("Unknown denomination: {denomination}.")),
+        }
+    }
+
+    /// Total value of the purse in copper pieces.
+    fn total(&self) -> Res<i64> {
+        self.coins
+            .iter()
+            .try_fold(0i64, |acc, &(_, value, count)| {
+                acc.checked_add(value.checked_mul(count)?)
+            })
+            .ok_or_else(|| OVERFLOW.to_string())
+    }
+
+    /// Pay an amount of the denomination at index, which the purse must be
+    /// able to afford. Larger coins are broken into this denomination as
+    /// needed, and any remaining shortfall is paid in smaller coins.
+    fn pay(&mut self, index: usize, amount: i64) {
+        while self.coins[index].2 < amount {
+            let Some(larger) = (index + 1..self.coins.len()).find(|&i| self.coins[i].2 > 0) else {
+                break;
+            };
+            self.coins[larger].2 -= 1;
+            self.coins[index].2 += self.coins[larger].1 / self.coins[index].1;
+        }
+
+        let paid = amount.min(self.coins[index].2);
+        self.coins[index].2 -= paid;
+        if paid < amount && index > 0 {
+            let ratio = self.coins[index].1 / self.coins[index - 1].1;
+            self.pay(index - 1, (amount - paid) * ratio);
+        }
+    }
+}
+
+fn check_amount(amount: i64) -> Res<()> {
+    if amount < 0 {
+        err("Amount of currency cannot be negative.")
+    } else {
+        Ok(())
+    }
+}
+
+/// Add coins of a denomination, returning the new count of that
+/// denomination.
+pub fn gain(context: &mut Context, amount: i64, denomination: &str) -> Res<i64> {
+    check_amount(amount)?;
+    let name = Purse::variable(denomination);
+    if !DENOMINATIONS.iter().any(|&(d, _)| d == denomination) {
+        return Err(format!("Unknown denomination: {denomination}."));
+    }
+
+    let count = match context.get_global(&name) {
+        Some(count) => count.clone().natural()?,
+        None => 0,
+    }
+    .checked_add(amount)
+    .ok_or(OVERFLOW)?;
+    context.set_variable(Context::GLOBAL_SCOPE, name, Value::Natural(count));
+    Ok(count)
+}
+
+/// Spend an amount of a denomination, making change from other denominations
+/// if necessary. Returns false, spending nothing, if the total wealth is
+/// insufficient.
+pub fn spend(context: &mut Context, amount: i64, denomination: &str) -> Res<bool> {
+    check_amount(amount)?;
+    let mut purse = Purse::load(context)?;
+
+    // Without electrum, electrum prices are paid in silver.
+    let (amount, denomination) = if denomination == ELECTRUM && purse.index(ELECTRUM).is_err() {
+        (amount.checked_mul(5).ok_or(OVERFLOW)?, "sp")
+    } else {
+        (amount, denomination)
+    };
+
+    let index = purse.index(denomination)?;
+    let cost = amount.checked_mul(purse.coins[index].1).ok_or(OVERFLOW)?;
+    if purse.total()? < cost {
+        return Ok(false);
+    }
+
+    purse.pay(index, amount);
+    purse.store(context);
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn purse(context: &mut Context, coins: &[(&str, i64)]) {
+        for &(denomination, count) in coins {
+            context.set_variable(
+                Context::GLOBAL_SCOPE,
+                Purse::variable(denomination),
+                Value::Natural(count),
+            );
+        }
+    }
+
+    fn count(context: &Context, denomination: &str) -> i64 {
+        context
+            .get_global(&Purse::variable(denomination))
+            .cloned()
+            .unwrap()
+            .natural()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_spend_across_denominations() {
+        let context = &mut Context::empty();
+        purse(context, &[("cp", 0), ("sp", 0), ("gp", 1), ("pp", 1)]);
+        assert!(spend(context, 5, "sp").unwrap());
+        assert_eq!(count(context, "gp"), 0);
+        assert_eq!(count(context, "sp"), 5);
+        assert_eq!(count(context, "pp"), 1);
+
+        assert!(spend(context, 2, "gp").unwrap());
+        assert_eq!(count(context, "pp"), 0);
+        assert_eq!(count(context, "gp"), 8);
+
+        purse(context, &[("cp", 50), ("sp", 5), ("gp", 0), ("pp", 0)]);
+        assert!(spend(context, 1, "gp").unwrap());
+        assert_eq!(count(context, "sp"), 0);
+        assert_eq!(count(context, "cp"), 0);
+    }
+
+    #[test]
+    fn test_spend_insufficient() {
+        let context = &mut Context::empty();
+        purse(context, &[("cp", 9), ("sp", 9), ("gp", 0), ("pp", 0)]);
+        assert!(!spend(context, 1, "gp").unwrap());
+        assert_eq!(count(context, "cp"), 9);
+        assert_eq!(count(context, "sp"), 9);
+    }
+
+    #[test]
+    fn test_spend_electrum() {
+        let context = &mut Context::empty();
+        purse(context, &[("sp", 10), ("ep", 1)]);
+        assert!(spend(context, 1, "ep").unwrap());
+        assert_eq!(count(context, "sp"), 5);
+        assert_eq!(count(context, "ep"), 1);
+
+        context.set_variable(Context::GLOBAL_SCOPE, USE_ELECTRUM_VAR, Value::Bool(true));
+        assert!(spend(context, 1, "ep").unwrap());
+        assert_eq!(count(context, "ep"), 0);
+    }
+
+    #[test]
+    fn test_gain() {
+        let context = &mut Context::empty();
+        assert_eq!(gain(context, 3, "gp"), Ok(3));
+        assert_eq!(gain(context, 2, "gp"), Ok(5));
+        assert!(gain(context, 2, "xp").is_err());
+        assert!(gain(context, -1, "gp").is_err());
+        assert_eq!(gain(context, i64::MAX, "gp"), Err(OVERFLOW.to_string()));
+        assert_eq!(count(context, "gp"), 5);
+    }
+
+    #[test]
+    fn test_spend_overflow() {
+        let context = &mut Context::empty();
+        purse(context, &[("pp", i64::MAX), ("gp", i64::MAX)]);
+        let overflow = Err(OVERFLOW.to_string());
+        assert_eq!(spend(context, i64::MAX, "pp"), overflow);
+        assert_eq!(spend(context, 1, "gp"), overflow);
+        assert_eq!(spend(context, i64::MAX, "ep"), overflow);
+        assert_eq!(count(context, "pp"), i64::MAX);
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use crate::roll::Roll;
+
+// Most outcomes a distribution may have before it is considered too complex
+// to compute exactly.
+const MAX_OUTCOMES: usize = 10_000;
+
+// Most pairs of outcomes to consider when combining two distributions.
+const MAX_PAIRS: usize = 1_000_000;
+
+/// Exact probability distribution of an integer valued expression.
+#[derive(Debug, PartialEq)]
+pub struct Distribution(BTreeMap<i64, f64>); // Value, probability.
+
+impl Distribution {
+    pub fn constant(value: i64) -> Self {
+        Self(BTreeMap::from([(value, 1.0)]))
+    }
+
+    fn uniform(die: u64) -> Option<Self> {
+        if die == 0 || die as usize > MAX_OUTCOMES {
+            return None;
+        }
+
+        let p = 1.0 / die as f64;
+        Some(Self((1..=die as i64).map(|v| (v, p)).collect()))
+    }
+
+    /// Distribution of the result of a roll, or None if it has too many
+    /// outcomes to compute.
+    pub fn roll(roll: &Roll) -> Option<Self> {
+        let die = Self::uniform(roll.die)?;
+        if roll.quantity as usize > MAX_OUTCOMES || roll.explode.is_some() {
+            return None;
+        }
+
+        if roll.takes_highest() || roll.takes_lowest() {
+            // The highest or lowest of the dice is taken.
+            let quantity = roll.dice() as i32;
+            let n = roll.die as f64;
+            let at_most = |k: f64| (k / n).powi(quantity);
+            let at_least = |k: f64| ((n - k + 1.0) / n).powi(quantity);
+            Some(Self(
+                die.0
+                    .into_keys()
+                    .map(|v| {
+                        let k = v as f64;
+                        let p = if roll.takes_highest() {
+                            at_most(k) - at_most(k - 1.0)
+                        } else {
+                            at_least(k) - at_least(k + 1.0)
+                        };
+                        (v, p)
+                    })
+                    .collect(),
+            ))
+        } else {
+            let mut total = Self::constant(0);
+            for _ in 0..roll.quantity {
+                total = total.combine(&die, i64::checked_add)?;
+            }
+            Some(total)
+        }
+    }
+
+    /// Distribution of the result of applying an operation to an outcome of
+    /// each distribution. None if the result is too complex or overflows.
+    pub fn combine<F: Fn(i64, i64) -> Option<i64>>(&self, other: &Self, f: F) -> Option<Self> {
+        if self.0.len() * other.0.len() > MAX_PAIRS {
+            return None;
+        }
+
+        let mut outcomes = BTreeMap::new();
+        for (&a, &pa) in &self.0 {
+            for (&b, &pb) in &other.0 {
+                *outcomes.entry(f(a, b)?).or_insert(0.0) += pa * pb;
+            }
+        }
+
+        if outcomes.len() > MAX_OUTCOMES {
+            None
+        } else {
+            Some(Self(outcomes))
+        }
+    }
+
+    /// Probability that an outcome is at least the target.
+    pub fn at_least(&self, target: f64) -> f64 {
+        self.0
+            .iter()
+            .filter(|&(&v, _)| v as f64 >= target)
+            .map(|(_, p)| p)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_roll() {
+        let d20 = Distribution::roll(&Roll::new(1, 20)).unwrap();
+        assert!(close(d20.at_least(15.0), 0.3));
+        assert!(close(d20.at_least(1.0), 1.0));
+        assert!(close(d20.at_least(21.0), 0.0));
+
+        let two_d6 = Distribution::roll(&Roll::new(2, 6)).unwrap();
+        assert!(close(two_d6.at_least(7.0), 21.0 / 36.0));
+        assert!(close(two_d6.at_least(12.0), 1.0 / 36.0));
+    }
+
+    #[test]
+    fn test_advantage() {
+        let mut roll = Roll::new(1, 20);
+        roll.advantage = 1;
+        let adv = Distribution::roll(&roll).unwrap();
+        assert!(close(adv.at_least(15.0), 1.0 - 0.7 * 0.7));
+        assert!(close(adv.at_least(1.0), 1.0));
+
+        roll.advantage = 2;
+        let adv = Distribution::roll(&roll).unwrap();
+        assert!(close(adv.at_least(15.0), 1.0 - 0.7 * 0.7 * 0.7));
+
+        roll.advantage = 0;
+        roll.disadvantage = true;
+        let disadv = Distribution::roll(&roll).unwrap();
+        assert!(close(disadv.at_least(15.0), 0.3 * 0.3));
+        assert!(close(disadv.at_least(1.0), 1.0));
+    }
+
+    #[test]
+    fn test_combine() {
+        let d4 = Distribution::roll(&Roll::new(1, 4)).unwrap();
+        let plus_two = d4
+            .combine(&Distribution::constant(2), i64::checked_add)
+            .unwrap();
+        assert!(close(plus_two.at_least(6.0), 0.25));
+        assert!(d4
+            .combine(&Distribution::constant(i64::MAX), i64::checked_add)
+            .is_none());
+        assert!(Distribution::roll(&Roll::new(1000, 1000)).is_none());
+    }
+}
@@ -1,10 +1,29 @@
-use crate::{context::Context, err, eval_tome, operator::Operator, outcome::Outcome, Res};
+use crate::{
+    context::Context,
+    distribution::Distribution,
+    err, eval_tome,
+    operator::Operator,
+    outcome::Outcome,
+    roll::{Explode, Roll},
+    Res,
+};
 
 use super::{
     ast::{Ast, Node},
     value::Value,
 };
 
+// Name of the form which computes the chance of an expression meeting a
+// target.
+const CHANCE: &str = "chance";
+
+// Number of times to evaluate an expression to estimate a chance which cannot
+// be computed exactly.
+pub const CHANCE_SAMPLES: usize = 10_000;
+
+// Start of the error raised when a variable is not defined.
+const UNDEFINED: &str = "Undefined variable";
+
 struct EvalCtx<'a> {
     ast: &'a Ast,
     context: &'a mut Context,
@@ -21,7 +40,13 @@ pub fn check_argument_count(name: &str, count: usize, args: &[Value]) -> Res<()>
     }
 }
 
-fn define_func(ctx: &mut EvalCtx, name: &str, args: &[usize], definition: usize) -> Res<Outcome> {
+fn define_func(
+    ctx: &mut EvalCtx,
+    name: &str,
+    args: &[usize],
+    definition: usize,
+    memo: bool,
+) -> Res<Outcome> {
     let mut parameters = Vec::new();
     for &arg in args {
         let Some(Node::Identifier(name)) = ctx.ast.get(arg) else {
@@ -38,119 +63,345 @@ fn define_func(ctx: &mut EvalCtx, name: &str, args: &[usize], definition: usize)
     };
 
     ctx.context
-        .define_function(ctx.scope, name, body, parameters);
+        .define_function(ctx.scope, name, body, parameters, memo);
     Ok(Outcome::empty())
 }
 
-fn assign(ctx: &mut EvalCtx, destination: usize, definition: usize) -> Res<Outcome> {
+/// A unit of pending work for the evaluator. Rather than recursing through
+/// the tree, a node is evaluated by scheduling a task to combine the outcomes
+/// of its operands, followed by tasks to evaluate each of those operands. This
+/// keeps the depth of expressions which can be evaluated bounded by the heap
+/// rather than the call stack.
+enum Task {
+    /// Evaluate a node, pushing its outcome onto the outcome stack.
+    Eval(usize),
+    /// Pop the given number of outcomes into a list.
+    List(usize),
+    /// Pop the given number of key and value outcome pairs into a map.
+    Map(usize),
+    /// Pop a key and a collection and push the element of that key.
+    Subscript,
+    /// Pop the arguments of the call node and call the function.
+    Call(usize),
+    /// Pop two operands and apply the operator of the binary node.
+    Binary(usize),
+    /// Pop the left operand of an and or or node, and evaluate the right
+    /// operand only if it can change the result.
+    ShortCircuit(usize),
+    /// Pop an operand and apply the operator of the unary node.
+    Unary(usize),
+    /// Pop a value and store it in the destination node.
+    Assign(usize),
+    /// Pop a condition and evaluate the block if true, or else the fallback.
+    Condition(usize, Option<usize>),
+    /// Try an arm (by index) of a match node against the scrutinee at the top
+    /// of the outcome stack.
+    MatchArm(usize, usize),
+    /// Pop the pattern of an arm (by index) of a match node and compare it
+    /// against the scrutinee.
+    MatchPattern(usize, usize),
+    /// Pop a path and import the tome at that path.
+    Import,
+    /// Pop a target and compute the chance that the first argument of the
+    /// call node meets it.
+    Chance(usize),
+    /// Record the outcome at the top of the stack as that of the node.
+    Trace(usize),
+    /// Pop an outcome and push it again rolled, so that errors rolling it
+    /// are raised.
+    Resolve,
+    /// Mark the point to unwind to if an error is raised by the tasks above,
+    /// evaluating the fallback node (by index) instead. Also holds the number
+    /// of outcomes on the stack when it was scheduled, and whether only
+    /// undefined variable errors are caught.
+    Catch(usize, usize, bool),
+}
+
+struct Evaluation {
+    tasks: Vec<Task>,
+    outcomes: Vec<Outcome>,
+}
+
+impl Evaluation {
+    fn new(index: usize) -> Self {
+        Self {
+            tasks: vec![Task::Eval(index)],
+            outcomes: Vec::new(),
+        }
+    }
+
+    fn schedule(&mut self, task: Task) {
+        self.tasks.push(task);
+    }
+
+    /// Schedule evaluation of the given nodes such that they will be
+    /// evaluated in order, leaving their outcomes on the stack in order.
+    fn schedule_nodes<I: DoubleEndedIterator<Item = usize>>(&mut self, nodes: I) {
+        for node in nodes.rev() {
+            self.tasks.push(Task::Eval(node));
+        }
+    }
+
+    fn push(&mut self, outcome: Outcome) {
+        self.outcomes.push(outcome);
+    }
+
+    fn pop(&mut self) -> Res<Outcome> {
+        if let Some(outcome) = self.outcomes.pop() {
+            Ok(outcome)
+        } else {
+            err("Attempted to pop empty outcome stack.")
+        }
+    }
+
+    fn pop_n(&mut self, n: usize) -> Res<Vec<Outcome>> {
+        if n > self.outcomes.len() {
+            err("Attempted to pop more outcomes than were on the stack.")
+        } else {
+            Ok(self.outcomes.split_off(self.outcomes.len() - n))
+        }
+    }
+
+    /// Discard tasks up to the nearest catch of the error, and any outcomes
+    /// pushed since it was scheduled, then schedule its fallback. Returns the
+    /// error if nothing catches it.
+    fn unwind(&mut self, error: String) -> Res<()> {
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Catch(fallback, outcomes, undefined)
+                    if !undefined || error.starts_with(UNDEFINED) =>
+                {
+                    self.outcomes.truncate(outcomes);
+                    self.schedule(Task::Eval(fallback));
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        Err(error)
+    }
+
+    fn peek(&self) -> Res<&Outcome> {
+        if let Some(outcome) = self.outcomes.last() {
+            Ok(outcome)
+        } else {
+            err("Attempted to peek empty outcome stack.")
+        }
+    }
+}
+
+/// Store a value in the variable or list element or map entry described by
+/// the destination node. Indexed destinations are updated with
+/// `Value::set` and the updated collection stored in turn.
+fn store(ctx: &mut EvalCtx, destination: usize, value: Value) -> Res<()> {
     match ctx.ast.get(destination) {
         Some(Node::Identifier(name)) => {
-            let value = evaluate_node(ctx, definition)?.value;
-            ctx.context.set_variable(ctx.scope, name, value.clone());
-            Ok(Outcome::new(value))
+            local(name)?;
+            ctx.context.set_variable(ctx.scope, name, value);
+            Ok(())
+        }
+        Some(&Node::Index(target, index)) => {
+            let key = evaluate_node(ctx, index)?.value;
+            let collection = evaluate_node(ctx, target)?.value;
+            store(ctx, target, collection.set(key, value)?)
         }
         invalid => err(format!("{invalid:?} is not a valid assignment target.")),
     }
 }
 
-fn define(ctx: &mut EvalCtx, signature: usize, definition: usize) -> Res<Outcome> {
+/// Check that a name being defined isn't qualified with a module, as members
+/// of a module are only defined in its body.
+fn local(name: &str) -> Res<()> {
+    if name.contains('.') {
+        err(format!(
+            "Cannot define {name} outside of its module, define it in a module block."
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn define(ctx: &mut EvalCtx, signature: usize, definition: usize, memo: bool) -> Res<Outcome> {
     match ctx.ast.get(signature) {
-        Some(Node::Call(name, args)) => define_func(ctx, name, args, definition),
+        Some(Node::Call(name, args)) => {
+            local(name)?;
+            define_func(ctx, name, args, definition, memo)
+        }
         invalid => err(format!("{invalid:?} is not a valid function signature.")),
     }
 }
 
-fn call(ctx: &mut EvalCtx, name: &str, args: &[usize]) -> Res<Outcome> {
-    let mut arg_values = Vec::new();
-    for arg in args {
-        arg_values.push(evaluate_node(ctx, *arg)?.value);
+/// Evaluate the statements of a module in its scope, creating it if this is
+/// its first definition.
+fn module(ctx: &mut EvalCtx, name: &str, body: &[usize]) -> Res<Outcome> {
+    let scope = ctx.context.module(ctx.scope, name)?;
+    for &statement in body {
+        evaluate_node(
+            &mut EvalCtx {
+                ast: ctx.ast,
+                context: ctx.context,
+                scope,
+            },
+            statement,
+        )?;
+    }
+    Ok(Outcome::empty())
+}
+
+/// Evaluate a block's statements in a new scope, returning the value of the
+/// last. Variables first assigned in the block are local to it.
+fn block(ctx: &mut EvalCtx, statements: &[usize]) -> Res<Outcome> {
+    let scope = ctx.context.child_scope(ctx.scope);
+    let mut ret = Ok(Outcome::empty());
+    for &statement in statements {
+        ret = evaluate_node(
+            &mut EvalCtx {
+                ast: ctx.ast,
+                context: ctx.context,
+                scope,
+            },
+            statement,
+        );
+        if ret.is_err() {
+            break;
+        }
     }
-    ctx.context.call(ctx.scope, name, arg_values)
+    ctx.context.pop_scope();
+    ret
+}
+
+fn call(ctx: &mut EvalCtx, name: &str, args: Vec<Outcome>) -> Res<Outcome> {
+    let mut rolls = Vec::new();
+    let arg_values = args
+        .into_iter()
+        .map(|mut oc| {
+            rolls.append(&mut oc.rolls);
+            oc.value
+        })
+        .collect();
+    let mut outcome = ctx.context.call(ctx.scope, name, arg_values)?;
+    rolls.append(&mut outcome.rolls);
+    outcome.rolls = rolls;
+    Ok(outcome)
+}
+
+/// A roll of a quantity of the context's default die.
+fn default_roll(ctx: &EvalCtx, quantity: u64) -> Roll {
+    Roll::new(quantity, ctx.context.default_die())
 }
 
 /// Attempts to return the value of the given name in the current context. If
 /// not found attempts to call a function with the given name with no
-/// parameters.
+/// parameters. A bare d which isn't defined is a roll of the default die.
 fn variable(ctx: &mut EvalCtx, name: &str) -> Res<Outcome> {
     if let Some(value) = ctx.context.get_variable(ctx.scope, name) {
         return Ok(Outcome::new(value.clone()));
-    } else {
-        let call_res = call(ctx, name, &[]);
-        if call_res.is_ok() {
-            return call_res;
-        }
+    } else if ctx.context.takes_arguments(ctx.scope, name) {
+        return Ok(Outcome::new(Value::Function(name.to_string())));
+    } else if ctx.context.is_function(ctx.scope, name) {
+        return call(ctx, name, Vec::new());
+    }
+
+    if name == "d" {
+        return Ok(Outcome::new(Value::Roll(default_roll(ctx, 1))));
     }
-    err(format!("Undefined variable: {name}."))
+    err(format!("{UNDEFINED}: {name}."))
 }
 
-fn list(ctx: &mut EvalCtx, values: &[usize]) -> Res<Outcome> {
-    let mut list = Vec::new();
-    for &index in values {
-        let val = evaluate_node(ctx, index)?;
-        list.push(val.value);
+fn list(values: Vec<Outcome>) -> Outcome {
+    Outcome::new(Value::List(values.into_iter().map(|oc| oc.value).collect()))
+}
+
+fn map(entries: Vec<Outcome>) -> Res<Outcome> {
+    let mut map = Value::Map(Vec::new());
+    let mut entries = entries.into_iter();
+    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+        map = map.set(key.value, value.value)?;
     }
-    Ok(Outcome::new(Value::List(list)))
+    Ok(Outcome::new(map))
 }
 
-fn binary(ctx: &mut EvalCtx, op: Operator, lhs: usize, rhs: usize) -> Res<Outcome> {
-    if matches!(op, Operator::Assign) {
-        assign(ctx, lhs, rhs)
-    } else if matches!(op, Operator::Define) {
-        define(ctx, lhs, rhs)
-    } else {
-        let lhs_val = evaluate_node(ctx, lhs)?;
-        let rhs_val = evaluate_node(ctx, rhs)?;
-        match op {
-            Operator::Assign => err("Operator::Assign doesn't match Operator::Assign."),
-            Operator::Define => err("Operator::Define doesn't match Operator::Define."),
-            Operator::Discard => Ok(rhs_val),
-            Operator::And => lhs_val.and(rhs_val),
-            Operator::Or => lhs_val.or(rhs_val),
-            Operator::Add => lhs_val.add(rhs_val),
-            Operator::Sub => lhs_val.sub(rhs_val),
-            Operator::Mul => lhs_val.mul(rhs_val),
-            Operator::Div => lhs_val.div(rhs_val),
-            Operator::Exp => lhs_val.exp(rhs_val),
-            Operator::Keep => lhs_val.keep(rhs_val),
-            Operator::Equal => lhs_val.equal(rhs_val),
-            Operator::GreaterThan => lhs_val.greater_than(rhs_val),
-            Operator::LessThan => lhs_val.less_than(rhs_val),
-            Operator::GreaterEqual => lhs_val.greater_equal(rhs_val),
-            Operator::LessEqual => lhs_val.less_equal(rhs_val),
-            Operator::Sentinel
-            | Operator::Not
-            | Operator::Neg
-            | Operator::Adv
-            | Operator::DisAdv => Err(format!("Not a binary operator: {}", op.str())),
-        }
-    }
-}
-
-fn unary(ctx: &mut EvalCtx, op: Operator, arg: usize) -> Res<Outcome> {
-    let val = evaluate_node(ctx, arg)?;
+fn subscript(target: Outcome, mut key: Outcome) -> Res<Outcome> {
+    let mut target = target.resolved()?;
+    target.rolls.append(&mut key.rolls);
+    Ok(Outcome {
+        value: target.value.get(key.value)?,
+        rolls: target.rolls,
+        label: None,
+    })
+}
+
+fn binary(op: Operator, lhs_val: Outcome, rhs_val: Outcome) -> Res<Outcome> {
+    match op {
+        Operator::Discard => Ok(rhs_val),
+        Operator::And => lhs_val.and(rhs_val),
+        Operator::Or => lhs_val.or(rhs_val),
+        Operator::Add => lhs_val.add(rhs_val),
+        Operator::Sub => lhs_val.sub(rhs_val),
+        Operator::Mul => lhs_val.mul(rhs_val),
+        Operator::Div => lhs_val.div(rhs_val),
+        Operator::FloorDiv => lhs_val.floor_div(rhs_val),
+        Operator::Mod => lhs_val.modulo(rhs_val),
+        Operator::Exp => lhs_val.exp(rhs_val),
+        Operator::Range => lhs_val.range(rhs_val, false),
+        Operator::RangeInclusive => lhs_val.range(rhs_val, true),
+        Operator::Keep => lhs_val.keep(rhs_val),
+        Operator::ExplodeAt => lhs_val.explode_at(Explode::Separate, rhs_val),
+        Operator::CompoundAt => lhs_val.explode_at(Explode::Compound, rhs_val),
+        Operator::Equal => lhs_val.equal(rhs_val),
+        Operator::GreaterThan => lhs_val.greater_than(rhs_val),
+        Operator::LessThan => lhs_val.less_than(rhs_val),
+        Operator::GreaterEqual => lhs_val.greater_equal(rhs_val),
+        Operator::LessEqual => lhs_val.less_equal(rhs_val),
+        Operator::Assign
+        | Operator::Define
+        | Operator::Sentinel
+        | Operator::Coalesce
+        | Operator::Not
+        | Operator::Neg
+        | Operator::Adv
+        | Operator::DisAdv
+        | Operator::Explode
+        | Operator::Compound => Err(format!("Not a binary operator: {}", op.str())),
+    }
+}
+
+fn unary(op: Operator, val: Outcome) -> Res<Outcome> {
     match op {
         Operator::Not => val.not(),
         Operator::Neg => val.neg(),
         Operator::Adv => val.adv(),
         Operator::DisAdv => val.disadv(),
+        Operator::Explode => val.explode(Explode::Separate),
+        Operator::Compound => val.explode(Explode::Compound),
         _ => Err(format!("Not a unary operator: {}", op.str())),
     }
 }
 
-fn condition(ctx: &mut EvalCtx, cond: usize, block: usize, fail: Option<usize>) -> Res<Outcome> {
-    let condition = evaluate_node(ctx, cond)?.value.bool()?;
-    if condition {
-        evaluate_node(ctx, block)
-    } else if let Some(node) = fail {
-        evaluate_node(ctx, node)
+/// Raise a descriptive error if an operand is an if without an else which had
+/// no value because its condition was false.
+fn check_operand(ctx: &EvalCtx, node: usize, operand: &Outcome) -> Res<()> {
+    if let Value::Empty = operand.value
+        && let Some(Node::If(_, _, None)) = ctx.ast.get(node)
+    {
+        Err(format!(
+            "{} has no value as its condition is false. Add an else to give it one.",
+            ctx.ast.render_node(node)
+        ))
     } else {
-        Ok(Outcome::new(Value::Empty))
+        Ok(())
     }
 }
 
-fn import(ctx: &mut EvalCtx, name: usize) -> Res<Outcome> {
-    let outcome = evaluate_node(ctx, name)?;
-    let name = outcome.value.string()?;
+fn match_arms<'a>(ctx: &EvalCtx<'a>, node: usize) -> Res<&'a [(Option<usize>, usize)]> {
+    match ctx.ast.get(node) {
+        Some(Node::Match(_, arms)) => Ok(arms),
+        _ => err("Attempted to match against non-match node."),
+    }
+}
+
+fn import(ctx: &mut EvalCtx, name: Outcome) -> Res<Outcome> {
+    let name = name.value.string()?;
     match std::fs::read_to_string(&name) {
         Ok(input) => {
             eval_tome(&input, ctx.context)?;
@@ -160,21 +411,308 @@ fn import(ctx: &mut EvalCtx, name: usize) -> Res<Outcome> {
     }
 }
 
+/// The roll a node evaluates to, if it is a roll literal, a variable holding a
+/// roll or a roll with advantage or disadvantage.
+fn roll_of(ctx: &EvalCtx, node: usize) -> Option<Roll> {
+    match ctx.ast.get(node)? {
+        Node::Value(Value::Roll(roll)) => Some(roll.clone()),
+        &Node::DefaultRoll(quantity) => Some(default_roll(ctx, quantity)),
+        Node::Identifier(name) => ctx
+            .context
+            .get_variable(ctx.scope, name)?
+            .clone()
+            .roll()
+            .ok(),
+        &Node::Unary(arg, Operator::Adv) => roll_of(ctx, arg).map(|mut roll| {
+            roll.advantage = roll.advantage.saturating_add(1);
+            roll
+        }),
+        &Node::Unary(arg, Operator::DisAdv) => roll_of(ctx, arg).map(|mut roll| {
+            roll.disadvantage = true;
+            roll
+        }),
+        _ => None,
+    }
+}
+
+/// Exact distribution of the value of a node, if it is a simple enough
+/// expression of rolls and constants to compute.
+fn distribution(ctx: &EvalCtx, node: usize) -> Option<Distribution> {
+    if let Some(roll) = roll_of(ctx, node) {
+        return Distribution::roll(&roll);
+    }
+
+    let constant = |value: &Value| match *value {
+        Value::Natural(n) => Some(Distribution::constant(n)),
+        Value::Decimal(v) if v.fract() == 0.0 => Some(Distribution::constant(v as i64)),
+        _ => None,
+    };
+
+    match ctx.ast.get(node)? {
+        Node::Value(value) => constant(value),
+        Node::Identifier(name) => constant(ctx.context.get_variable(ctx.scope, name)?),
+        &Node::Binary(lhs, op, rhs) => {
+            let f = match op {
+                Operator::Add => i64::checked_add,
+                Operator::Sub => i64::checked_sub,
+                Operator::Mul => i64::checked_mul,
+                _ => return None,
+            };
+            distribution(ctx, lhs)?.combine(&distribution(ctx, rhs)?, f)
+        }
+        &Node::Unary(arg, Operator::Neg) => {
+            Distribution::constant(0).combine(&distribution(ctx, arg)?, i64::checked_sub)
+        }
+        _ => None,
+    }
+}
+
+/// Probability that the value of a node is at least the target. This is
+/// computed exactly where possible, or else estimated by evaluating the node
+/// repeatedly.
+fn chance(ctx: &mut EvalCtx, node: usize, target: f64) -> Res<Outcome> {
+    let probability = match distribution(ctx, node) {
+        Some(distribution) => distribution.at_least(target),
+        None => {
+            let mut successes = 0;
+            for _ in 0..CHANCE_SAMPLES {
+                if evaluate_node(ctx, node)?.value.decimal()? >= target {
+                    successes += 1;
+                }
+            }
+            successes as f64 / CHANCE_SAMPLES as f64
+        }
+    };
+    Ok(Outcome::new(Value::Decimal(probability)))
+}
+
+/// Schedule the tasks to evaluate a node, or evaluate it immediately if it
+/// has no operands.
+fn expand(ctx: &mut EvalCtx, eval: &mut Evaluation, index: usize) -> Res<()> {
+    let Some(expr) = ctx.ast.get(index) else {
+        return err("Attempted to evaluate expression which did not exist.");
+    };
+
+    if ctx.context.tracing() {
+        ctx.context.trace_enter();
+        eval.schedule(Task::Trace(index));
+    }
+
+    match expr {
+        Node::Value(val) => eval.push(Outcome::new(val.clone())),
+        &Node::DefaultRoll(quantity) => {
+            eval.push(Outcome::new(Value::Roll(default_roll(ctx, quantity))))
+        }
+        Node::Identifier(name) => eval.push(variable(ctx, name)?),
+        Node::List(values) => {
+            eval.schedule(Task::List(values.len()));
+            eval.schedule_nodes(values.iter().copied());
+        }
+        Node::Map(entries) => {
+            eval.schedule(Task::Map(entries.len()));
+            eval.schedule_nodes(entries.iter().flat_map(|&(k, v)| [k, v]));
+        }
+        &Node::Index(target, index) => {
+            eval.schedule(Task::Subscript);
+            eval.schedule_nodes([target, index].into_iter());
+        }
+        &Node::Binary(lhs, Operator::Assign, rhs) => {
+            eval.schedule(Task::Assign(lhs));
+            eval.schedule(Task::Eval(rhs));
+        }
+        &Node::Binary(lhs, Operator::Define, rhs) => eval.push(define(ctx, lhs, rhs, false)?),
+        &Node::Memo(definition) => match ctx.ast.get(definition) {
+            Some(&Node::Binary(lhs, Operator::Define, rhs)) => {
+                eval.push(define(ctx, lhs, rhs, true)?)
+            }
+            invalid => return err(format!("{invalid:?} is not a function definition.")),
+        },
+        Node::Module(name, body) => eval.push(module(ctx, name, body)?),
+        Node::Block(statements) => eval.push(block(ctx, statements)?),
+        &Node::Binary(lhs, Operator::Coalesce, rhs) => {
+            eval.schedule(Task::Catch(rhs, eval.outcomes.len(), true));
+            eval.schedule(Task::Eval(lhs));
+        }
+        &Node::Binary(lhs, Operator::And | Operator::Or, _) => {
+            eval.schedule(Task::ShortCircuit(index));
+            eval.schedule(Task::Eval(lhs));
+        }
+        &Node::Binary(lhs, _, rhs) => {
+            eval.schedule(Task::Binary(index));
+            eval.schedule_nodes([lhs, rhs].into_iter());
+        }
+        &Node::Unary(arg, _) => {
+            eval.schedule(Task::Unary(index));
+            eval.schedule(Task::Eval(arg));
+        }
+        Node::Call(name, args) if name == CHANCE => {
+            if args.len() != 2 {
+                return err(format!(
+                    "Incorrect number of arguments: {CHANCE} expects 2."
+                ));
+            }
+            eval.schedule(Task::Chance(index));
+            eval.schedule(Task::Eval(args[1]));
+        }
+        Node::Call(_, args) => {
+            eval.schedule(Task::Call(index));
+            eval.schedule_nodes(args.iter().copied());
+        }
+        // A roll is rolled so that errors rolling it are caught.
+        &Node::Try(expr, fallback) => {
+            eval.schedule(Task::Catch(fallback, eval.outcomes.len(), false));
+            eval.schedule(Task::Resolve);
+            eval.schedule(Task::Eval(expr));
+        }
+        &Node::If(cond, expr, fail) => {
+            eval.schedule(Task::Condition(expr, fail));
+            eval.schedule(Task::Eval(cond));
+        }
+        &Node::Match(scrutinee, _) => {
+            eval.schedule(Task::MatchArm(index, 0));
+            eval.schedule(Task::Eval(scrutinee));
+        }
+        &Node::Import(name) => {
+            eval.schedule(Task::Import);
+            eval.schedule(Task::Eval(name));
+        }
+    }
+    Ok(())
+}
+
+fn step(ctx: &mut EvalCtx, eval: &mut Evaluation, task: Task) -> Res<()> {
+    match task {
+        Task::Eval(index) => expand(ctx, eval, index)?,
+        Task::List(n) => {
+            let values = eval.pop_n(n)?;
+            eval.push(list(values));
+        }
+        Task::Map(n) => {
+            let entries = eval.pop_n(n * 2)?;
+            eval.push(map(entries)?);
+        }
+        Task::Subscript => {
+            let key = eval.pop()?;
+            let target = eval.pop()?;
+            eval.push(subscript(target, key)?);
+        }
+        Task::Call(index) => {
+            let Some(Node::Call(name, args)) = ctx.ast.get(index) else {
+                return err("Attempted to call non-call node.");
+            };
+            let args = eval.pop_n(args.len())?;
+            eval.push(call(ctx, name, args)?);
+        }
+        Task::Binary(index) => {
+            let Some(&Node::Binary(lhs_node, op, rhs_node)) = ctx.ast.get(index) else {
+                return err("Attempted to apply non-binary node.");
+            };
+            let rhs = eval.pop()?;
+            let lhs = eval.pop()?;
+            if op != Operator::Discard {
+                check_operand(ctx, lhs_node, &lhs)?;
+                check_operand(ctx, rhs_node, &rhs)?;
+            }
+            eval.push(binary(op, lhs, rhs)?);
+        }
+        Task::ShortCircuit(index) => {
+            let Some(&Node::Binary(lhs_node, op, rhs_node)) = ctx.ast.get(index) else {
+                return err("Attempted to short circuit non-binary node.");
+            };
+            let lhs = eval.pop()?;
+            check_operand(ctx, lhs_node, &lhs)?;
+            let (lhs, value) = lhs.bool()?;
+            if value == (op == Operator::Or) {
+                eval.push(Outcome {
+                    value: Value::Bool(value),
+                    rolls: lhs.rolls,
+                    label: None,
+                });
+            } else {
+                eval.push(lhs);
+                eval.schedule(Task::Binary(index));
+                eval.schedule(Task::Eval(rhs_node));
+            }
+        }
+        Task::Unary(index) => {
+            let Some(&Node::Unary(arg_node, op)) = ctx.ast.get(index) else {
+                return err("Attempted to apply non-unary node.");
+            };
+            let arg = eval.pop()?;
+            check_operand(ctx, arg_node, &arg)?;
+            eval.push(unary(op, arg)?);
+        }
+        Task::Assign(destination) => {
+            let value = eval.pop()?.value;
+            store(ctx, destination, value.clone())?;
+            eval.push(Outcome::new(value));
+        }
+        Task::Condition(block, fail) => {
+            if eval.pop()?.value.bool()? {
+                eval.schedule(Task::Eval(block));
+            } else if let Some(node) = fail {
+                eval.schedule(Task::Eval(node));
+            } else {
+                eval.push(Outcome::new(Value::Empty));
+            }
+        }
+        Task::MatchArm(node, arm) => match match_arms(ctx, node)?.get(arm) {
+            Some(&(Some(pattern), _)) => {
+                eval.schedule(Task::MatchPattern(node, arm));
+                eval.schedule(Task::Eval(pattern));
+            }
+            Some(&(None, result)) => {
+                eval.pop()?;
+                eval.schedule(Task::Eval(result));
+            }
+            None => {
+                eval.pop()?;
+                eval.push(Outcome::new(Value::Empty));
+            }
+        },
+        Task::MatchPattern(node, arm) => {
+            let pattern = eval.pop()?;
+            if pattern.value == eval.peek()?.value {
+                eval.pop()?;
+                let &(_, result) = &match_arms(ctx, node)?[arm];
+                eval.schedule(Task::Eval(result));
+            } else {
+                eval.schedule(Task::MatchArm(node, arm + 1));
+            }
+        }
+        Task::Import => {
+            let name = eval.pop()?;
+            eval.push(import(ctx, name)?);
+        }
+        Task::Chance(index) => {
+            let Some(Node::Call(_, args)) = ctx.ast.get(index) else {
+                return err("Attempted to compute chance of non-call node.");
+            };
+            let target = eval.pop()?.value.decimal()?;
+            eval.push(chance(ctx, args[0], target)?);
+        }
+        Task::Trace(index) => {
+            let line = format!("{} => {}", ctx.ast.render_node(index), eval.peek()?.value);
+            ctx.context.trace_exit(line);
+        }
+        Task::Resolve => {
+            let outcome = eval.pop()?.resolved()?;
+            eval.push(outcome);
+        }
+        // Reached without an error, so there is nothing to catch.
+        Task::Catch(..) => {}
+    }
+    Ok(())
+}
+
 fn evaluate_node(ctx: &mut EvalCtx, index: usize) -> Res<Outcome> {
-    if let Some(expr) = ctx.ast.get(index) {
-        match expr {
-            Node::Value(val) => Ok(Outcome::new(val.clone())),
-            Node::Identifier(name) => variable(ctx, name),
-            Node::List(values) => list(ctx, values),
-            &Node::Binary(lhs, op, rhs) => binary(ctx, op, lhs, rhs),
-            &Node::Unary(arg, op) => unary(ctx, op, arg),
-            Node::Call(name, args) => call(ctx, name, args),
-            &Node::If(cond, expr, fail) => condition(ctx, cond, expr, fail),
-            &Node::Import(name) => import(ctx, name),
+    let mut eval = Evaluation::new(index);
+    while let Some(task) = eval.tasks.pop() {
+        if let Err(e) = step(ctx, &mut eval, task) {
+            eval.unwind(e)?;
         }
-    } else {
-        err("Attempted to evaluate expression which did not exist.")
     }
+    eval.pop()
 }
 
 pub fn evaluate(ast: &Ast, context: &mut Context, scope: usize) -> Res<Outcome> {
@@ -190,6 +728,22 @@ pub fn evaluate(ast: &Ast, context: &mut Context, scope: usize) -> Res<Outcome>
     }
 }
 
+/// Name of the variable or function a statement defines, if any.
+fn defined_name(ast: &Ast) -> Option<&str> {
+    let mut node = ast.get(ast.start())?;
+    if let &Node::Memo(definition) = node {
+        node = ast.get(definition)?;
+    }
+
+    let &Node::Binary(lhs, Operator::Assign | Operator::Define, _) = node else {
+        return None;
+    };
+    match ast.get(lhs)? {
+        Node::Identifier(name) | Node::Call(name, _) => Some(name),
+        _ => None,
+    }
+}
+
 pub fn evaluate_tome(statements: &[Ast], context: &mut Context, scope: usize) -> Res<()> {
     for statement in statements {
         evaluate_node(
@@ -200,6 +754,11 @@ pub fn evaluate_tome(statements: &[Ast], context: &mut Context, scope: usize) ->
             },
             statement.start(),
         )?;
+
+        // Keep comments on definitions so that they are saved with them.
+        if let (Some(name), Some(comment)) = (defined_name(statement), statement.comment()) {
+            context.set_comment(scope, name, comment);
+        }
     }
     Ok(())
 }
@@ -238,8 +797,10 @@ mod test {
             Value::Roll(Roll {
                 quantity: 4,
                 die: 12,
-                advantage: false,
-                disadvantage: false
+                advantage: 0,
+                disadvantage: false,
+                explode: None,
+                threshold: None,
             })
         )
     }
@@ -262,6 +823,222 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_if_without_else_operand() {
+        let eval = |input| evaluate(&ast_of(input), &mut Context::empty(), Context::GLOBAL_SCOPE);
+        assert_eq!(
+            eval("1 + (if false then 2)"),
+            err("if false then 2 has no value as its condition is false. Add an else to give it one.")
+        );
+        assert!(eval("-(if false then 2)").unwrap_err().contains("else"));
+        assert_eq!(eval("1 + (if true then 2)").unwrap().value.natural(), Ok(3));
+        assert_eq!(
+            eval("(if false then 2); 3").unwrap().value,
+            Value::Natural(3)
+        );
+    }
+
+    #[test]
+    fn test_chance() {
+        let context = &mut Context::empty();
+        let mut chance = |input| {
+            evaluate(&ast_of(input), context, Context::GLOBAL_SCOPE)
+                .unwrap()
+                .value
+                .decimal()
+                .unwrap()
+        };
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(close(chance("chance(d20, 15)"), 0.3));
+        assert!(close(chance("chance(d20 + 5, 20)"), 0.3));
+        assert!(close(chance("chance(2d6, 7)"), 21.0 / 36.0));
+        assert!(close(chance("chance(d20a, 15)"), 1.0 - 0.7 * 0.7));
+        assert!(close(chance("chance(-d4, -1)"), 0.25));
+        assert!(close(chance("hd = d8; chance(hd * 2, 16)"), 0.125));
+
+        // Estimated by sampling.
+        crate::rng::seed(1);
+        assert!(close(chance("chance(4d6k3, 3)"), 1.0));
+        assert!((chance("chance(d20 / 2, 5)") - 0.55).abs() < 0.05);
+
+        assert!(evaluate(&ast_of("chance(d20)"), context, Context::GLOBAL_SCOPE).is_err());
+    }
+
+    #[test]
+    fn test_floor_div_mod() {
+        let eval = |input: &str| eval(input, &mut Context::empty()).unwrap().value.natural();
+        // Division is floored, and the remainder has the sign of the divisor.
+        for (a, b, quotient, remainder) in [
+            (7, 3, 2, 1),
+            (-7, 3, -3, 2),
+            (7, -3, -3, -2),
+            (-7, -3, 2, -1),
+            (6, 3, 2, 0),
+            (-6, 3, -2, 0),
+            (0, -3, 0, 0),
+        ] {
+            assert_eq!(eval(&format!("({a}) // ({b})")), Ok(quotient));
+            assert_eq!(eval(&format!("({a}) % ({b})")), Ok(remainder));
+            assert_eq!(
+                eval(&format!("({b}) * (({a}) // ({b})) + ({a}) % ({b})")),
+                Ok(a)
+            );
+        }
+
+        assert_eq!(eval("7.5 // 2"), Ok(3));
+        assert_eq!(eval("2 + 7 % 4 * 2"), Ok(8));
+        assert_eq!(eval("-7 // 2"), Ok(-4));
+    }
+
+    #[test]
+    fn test_non_finite() {
+        let eval = |input| evaluate(&ast_of(input), &mut Context::empty(), Context::GLOBAL_SCOPE);
+        assert_eq!(eval("1 / 0"), err("Division by zero."));
+        assert_eq!(eval("0 / 0"), err("Division by zero."));
+        assert_eq!(eval("1 // 0"), err("Division by zero."));
+        assert_eq!(eval("1 % 0"), err("Division by zero."));
+        assert!(eval("10 ^ 400").is_err());
+        assert!(eval("(0 - 1) ^ 0.5").is_err());
+    }
+
+    #[test]
+    fn test_default_die() {
+        let context = &mut Context::empty();
+        let roll = |input, context: &mut Context| {
+            let outcome = eval(input, context).unwrap();
+            outcome.rolls[0].roll.clone()
+        };
+        assert_eq!(roll("4d", context), Roll::new(4, Context::DEFAULT_DIE));
+
+        context.set_default_die(6);
+        assert_eq!(roll("4d", context), Roll::new(4, 6));
+        assert_eq!(roll("d", context), Roll::new(1, 6));
+        assert_eq!(roll("roll()", context), Roll::new(1, 6));
+        assert_eq!(roll("roll(3)", context), Roll::new(3, 6));
+        assert_eq!(roll("roll(3, 8)", context), Roll::new(3, 8));
+        assert_eq!(roll("2d!", context).explode, Some(Explode::Separate));
+        assert_eq!(eval("d = 3; d", context).unwrap().value, Value::Natural(3));
+        assert_eq!(ast_of("2d + 1").render(), "2d + 1");
+    }
+
+    #[test]
+    fn test_zero_sided_die() {
+        let context = &mut Context::empty();
+        assert_eq!(eval("d0", context), err("Cannot roll a die with 0 sides."));
+        assert_eq!(
+            eval("2d0 + 1", context),
+            err("Cannot roll a die with 0 sides.")
+        );
+        assert!(eval("d1!", context).is_err());
+        assert!(eval("try d0 else 1", context).is_ok());
+    }
+
+    #[test]
+    fn test_try() {
+        let context = &mut Context::empty();
+        let mut eval = |input| eval(input, context).map(|outcome| outcome.value);
+        assert_eq!(eval("try 1 / 0 else 0"), Ok(Value::Natural(0)));
+        assert_eq!(eval("try 6 / 2 else 0").unwrap().natural(), Ok(3));
+        assert_eq!(
+            eval("x = (try undefined else 2); x + 1").unwrap().natural(),
+            Ok(3)
+        );
+        assert_eq!(
+            eval("try 1 / 0 else try [][0] else -1").unwrap().natural(),
+            Ok(-1)
+        );
+        assert_eq!(eval("try 1 / 0 else 1 / 0"), err("Division by zero."));
+        assert_eq!(eval("(try 1 / 0 else 2) * 3").unwrap().natural(), Ok(6));
+    }
+
+    #[test]
+    fn test_deeply_nested_try() {
+        const DEPTH: usize = 200;
+        let input = format!("{}1 / 0{}", "try ".repeat(DEPTH), " else 2".repeat(DEPTH));
+        assert_eq!(
+            eval(&input, &mut Context::empty()).unwrap().value,
+            Value::Natural(2)
+        );
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let context = &mut Context::empty();
+        let mut eval = |input| eval(input, context).map(|outcome| outcome.value);
+        assert_eq!(eval("x ?? 0"), Ok(Value::Natural(0)));
+        assert_eq!(eval("x = 3; x ?? 0"), Ok(Value::Natural(3)));
+        assert_eq!(eval("y ?? z ?? 1 + 1").unwrap().natural(), Ok(2));
+        assert_eq!(eval("(y + 1) ?? 5"), Ok(Value::Natural(5)));
+        assert_eq!(
+            eval(r#"(x - "a") ?? 0"#),
+            err("String cannot be interpreted as decimal.")
+        );
+        assert_eq!(eval("y ?? w"), err("Undefined variable: w."));
+        assert_eq!(eval("try (y ?? 1 / 0) else 3"), Ok(Value::Natural(3)));
+        assert_eq!(eval("(try y else 1 / 0) ?? 4"), err("Division by zero."));
+        assert_eq!(eval("1 + (y ?? 2) + (z ?? 3)").unwrap().natural(), Ok(6));
+
+        // Errors raised by a function called without arguments aren't hidden.
+        eval("f() := 1 / 0").unwrap();
+        assert_eq!(eval("f ?? 5"), err("Division by zero."));
+        assert_eq!(eval("f() ?? 5"), err("Division by zero."));
+        eval("g() := undefined").unwrap();
+        assert_eq!(eval("g ?? 6"), Ok(Value::Natural(6)));
+
+        let deep = format!("{}1", "y ?? ".repeat(200));
+        assert_eq!(eval(&deep), Ok(Value::Natural(1)));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let context = &mut Context::empty();
+        let mut eval = |input| eval(input, context).unwrap();
+        let outcome = eval("false & (x = d20 > 5)");
+        assert_eq!(outcome.value, Value::Bool(false));
+        assert!(outcome.rolls.is_empty());
+        assert_eq!(eval("true | (y = true)").value, Value::Bool(true));
+        assert_eq!(eval("true & (z = false)").value, Value::Bool(false));
+        assert_eq!(eval("false | (w = 1)").value, Value::Bool(true));
+        assert_eq!(eval("3d1 > 2 | d20 > 5").rolls.len(), 1);
+        assert_eq!(eval("2d1 > 2 | d1 > 0").rolls.len(), 2);
+
+        assert!(context.get_variable(Context::GLOBAL_SCOPE, "x").is_none());
+        assert!(context.get_variable(Context::GLOBAL_SCOPE, "y").is_none());
+        assert!(context.get_variable(Context::GLOBAL_SCOPE, "z").is_some());
+        assert!(context.get_variable(Context::GLOBAL_SCOPE, "w").is_some());
+    }
+
+    #[test]
+    fn test_advantage_and_disadvantage() {
+        let mut roll = Roll::new(1, 20);
+        roll.advantage = 1;
+        roll.disadvantage = true;
+        let eval = |input| evaluate(&ast_of(input), &mut Context::empty(), Context::GLOBAL_SCOPE);
+        assert_eq!(eval("d20ad").unwrap().value, Value::Roll(roll.clone()));
+        assert_eq!(eval("d20da").unwrap().value, Value::Roll(roll.clone()));
+
+        roll.advantage = 2;
+        roll.disadvantage = false;
+        assert_eq!(eval("d20aa").unwrap().value, Value::Roll(roll));
+    }
+
+    #[test]
+    fn test_explode_at() {
+        let eval = |input| evaluate(&ast_of(input), &mut Context::empty(), Context::GLOBAL_SCOPE);
+        let mut roll = Roll::new(3, 10);
+        roll.explode = Some(Explode::Compound);
+        roll.threshold = Some(8);
+        assert_eq!(
+            eval("3d10!!>=(4 * 2)").unwrap().value,
+            Value::Roll(roll.clone())
+        );
+        assert_eq!(roll.to_string(), "3d10!!>=8");
+        assert_eq!(
+            eval("d10!>=1"),
+            err("Explosion threshold must be at least 2.")
+        );
+    }
+
     #[test]
     fn test_rolls() {
         let result = evaluate(
@@ -281,14 +1058,18 @@ mod test {
                 Roll {
                     quantity: 1,
                     die: 20,
-                    advantage: false,
-                    disadvantage: true
+                    advantage: 0,
+                    disadvantage: true,
+                    explode: None,
+                    threshold: None,
                 },
                 Roll {
                     quantity: 2,
                     die: 10,
-                    advantage: true,
-                    disadvantage: false
+                    advantage: 1,
+                    disadvantage: false,
+                    explode: None,
+                    threshold: None,
                 }
             ]
         )
@@ -301,16 +1082,39 @@ mod test {
                 roll: Roll {
                     quantity: 8,
                     die: 8,
-                    advantage: false,
+                    advantage: 0,
                     disadvantage: false,
+                    explode: None,
+                    threshold: None,
                 },
                 rolls: vec![1, 2, 3, 4, 5, 6, 7, 8],
                 result: 36,
             }),
+            label: None,
             rolls: Vec::new(),
         };
         let values = expr.keep(Outcome::nat(6)).unwrap().value.rolls().unwrap();
         assert_eq!(values, vec![3, 4, 5, 6, 7, 8]);
+
+        let keep = |rolls: &[u64], keep| {
+            Outcome::new(Value::Rolls(rolls.to_vec()))
+                .keep(Outcome::nat(keep))
+                .map(|outcome| outcome.value)
+        };
+        assert_eq!(keep(&[2, 1, 5, 3], 2), Ok(Value::Rolls(vec![5, 3])));
+        assert_eq!(keep(&[4, 1, 4, 4], 2), Ok(Value::Rolls(vec![4, 4])));
+        assert_eq!(keep(&[2, 1], 3), Ok(Value::Rolls(vec![2, 1])));
+        assert_eq!(keep(&[2, 1, 5, 3], 0), Ok(Value::Rolls(Vec::new())));
+
+        let eval = |input| evaluate(&ast_of(input), &mut Context::empty(), Context::GLOBAL_SCOPE);
+        let outcome = eval("4d6k0").unwrap();
+        assert_eq!(outcome.value, Value::Rolls(Vec::new()));
+        assert_eq!(outcome.value.natural(), Ok(0));
+        assert_eq!(outcome.rolls[0].rolls.len(), 4);
+        assert_eq!(
+            eval("4d6k(-1)"),
+            err("Cannot keep a negative number of dice.")
+        );
     }
 
     #[test]
@@ -398,6 +1202,212 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_block() {
+        let context = &mut Context::empty();
+        assert_eq!(
+            eval("x = 1; y = { z = x + 1; x = z * 2; z + 1 }", context)
+                .unwrap()
+                .value
+                .natural()
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            context
+                .get_variable(Context::GLOBAL_SCOPE, "x")
+                .cloned()
+                .unwrap()
+                .natural()
+                .unwrap(),
+            4
+        );
+        assert_eq!(context.get_variable(Context::GLOBAL_SCOPE, "z"), None);
+
+        eval("f(x) := { t = x * 2; t + 1 }", context).unwrap();
+        assert_eq!(
+            eval("f(3) + y", context).unwrap().value.natural().unwrap(),
+            10
+        );
+        assert!(eval("{ w = 1; w / 0 }", context).is_err());
+        assert_eq!(context.get_variable(Context::GLOBAL_SCOPE, "w"), None);
+        assert!(eval("{ module m { a = 1 } }", context).is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        let context = &mut Context::empty();
+        assert_eq!(eval("2..5", context).unwrap().to_string(), "[2, 3, 4]");
+        assert_eq!(eval("2..=5", context).unwrap().to_string(), "[2, 3, 4, 5]");
+        assert_eq!(
+            eval("n = 2; 0..n + 1", context).unwrap().to_string(),
+            "[0, 1, 2]"
+        );
+        assert_eq!(eval("3..3", context).unwrap().to_string(), "[]");
+        assert_eq!(
+            eval("range(1, 4)", context).unwrap().to_string(),
+            "[1, 2, 3]"
+        );
+        assert!(eval("0..10000000000000", context).is_err());
+        assert!(eval("-9223372036854775807..9223372036854775807", context).is_err());
+        assert!(eval("range(0, 10000000000000)", context).is_err());
+        assert!(eval("1..=1000000", context).is_ok());
+    }
+
+    #[test]
+    fn test_map() {
+        let context = &mut Context::empty();
+        eval(
+            r#"stats = {"str": 16, "dex": 14, "saves": {"dex": 2}}"#,
+            context,
+        )
+        .unwrap();
+        assert_eq!(
+            eval(r#"get(stats, "dex")"#, context).unwrap().value,
+            Value::Natural(14)
+        );
+        assert_eq!(
+            eval(r#"get(get(stats, "saves"), "dex")"#, context)
+                .unwrap()
+                .value,
+            Value::Natural(2)
+        );
+        assert_eq!(
+            eval(r#"set(8, {"str": 16}, "str")"#, context)
+                .unwrap()
+                .to_string(),
+            r#"{"str": 8}"#
+        );
+        assert!(eval(r#"get(stats, "cha")"#, context).is_err());
+    }
+
+    #[test]
+    fn test_index() {
+        let context = &mut Context::empty();
+        eval(r#"xs = [1, [2, 3]]; m = {"a": 4}"#, context).unwrap();
+        assert_eq!(eval("xs[0]", context).unwrap().value, Value::Natural(1));
+        assert_eq!(eval("xs[1][0]", context).unwrap().value, Value::Natural(2));
+        assert_eq!(eval(r#"m["a"]"#, context).unwrap().value, Value::Natural(4));
+        assert_eq!(
+            eval(r#""abc"[2]"#, context).unwrap().value,
+            Value::String("c".into())
+        );
+        assert!(eval("xs[2]", context).is_err());
+    }
+
+    #[test]
+    fn test_index_assignment() {
+        let context = &mut Context::empty();
+        eval(r#"xs = [1, [2, 3]]; m = {"a": 4}"#, context).unwrap();
+        eval("xs[1][0] = 5", context).unwrap();
+        assert_eq!(eval("xs", context).unwrap().to_string(), "[1, [5, 3]]");
+        eval(r#"m["b"] = xs[0]"#, context).unwrap();
+        assert_eq!(
+            eval("m", context).unwrap().to_string(),
+            r#"{"a": 4, "b": 1}"#
+        );
+        assert!(eval("xs[5] = 1", context).is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        let context = &mut Context::empty();
+        eval(
+            r#"describe(x) := match x { 1 -> "one", "a" -> "letter", _ -> "other" }"#,
+            context,
+        )
+        .unwrap();
+        assert_eq!(
+            eval("describe(1)", context).unwrap().value,
+            Value::String("one".into())
+        );
+        assert_eq!(
+            eval(r#"describe("a")"#, context).unwrap().value,
+            Value::String("letter".into())
+        );
+        assert_eq!(
+            eval("describe([1])", context).unwrap().value,
+            Value::String("other".into())
+        );
+    }
+
+    #[test]
+    fn test_match_no_arm() {
+        assert_eq!(
+            eval("match 3 { 1 -> 2 }", &mut Context::empty())
+                .unwrap()
+                .value,
+            Value::Empty
+        );
+    }
+
+    #[test]
+    fn test_deeply_nested_expression() {
+        const TERMS: usize = 100_000;
+        let input = vec!["1"; TERMS].join(" + ");
+        assert_eq!(
+            eval(&input, &mut Context::empty())
+                .unwrap()
+                .value
+                .natural()
+                .unwrap(),
+            TERMS as i64
+        );
+    }
+
+    #[test]
+    fn test_evaluation_order() {
+        let context = &mut Context::empty();
+        assert_eq!(
+            eval("a = 1; [a = a * 2, a = a + 1, {a: a = a * 3}]", context)
+                .unwrap()
+                .to_string(),
+            "[2, 3, {3: 9}]"
+        );
+    }
+
+    #[test]
+    fn test_function_values() {
+        let context = &mut Context::empty();
+        eval("neg(x) := -x", context).unwrap();
+        assert_eq!(
+            eval("sort_by([1, 3, 2], neg)", context)
+                .unwrap()
+                .to_string(),
+            "[3, 2, 1]"
+        );
+        eval("f = neg", context).unwrap();
+        assert_eq!(eval("f(2)", context).unwrap().to_string(), "-2");
+        eval("g = upper", context).unwrap();
+        assert_eq!(
+            eval("g(\"a\")", context).unwrap().value,
+            Value::String("A".into())
+        );
+    }
+
+    #[test]
+    fn test_trace() {
+        let context = &mut Context::empty();
+        eval("f(x) := x * 2", context).unwrap();
+        context.set_tracing(true);
+        eval("1 + f(3)", context).unwrap();
+        assert_eq!(
+            context.take_trace(),
+            vec![
+                "  1 => 1",
+                "    3 => 3",
+                "      x => 3",
+                "      2 => 2",
+                "    x * 2 => 6",
+                "  f(3) => 6",
+                "1 + f(3) => 7",
+            ]
+        );
+        context.set_tracing(false);
+        eval("1 + 2", context).unwrap();
+        assert!(context.take_trace().is_empty());
+    }
+
     #[test]
     fn test_multiline_statement() {
         let mut context = &mut Context::empty();
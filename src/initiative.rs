@@ -0,0 +1,73 @@
+use std::{cmp::Reverse, fmt::Display};
+
+/// Turn order of a combat, highest initiative first.
+#[derive(Debug, PartialEq)]
+pub struct Tracker {
+    entrants: Vec<(String, i64)>, // (Name, initiative).
+    turn: usize,                  // Index of the entrant whose turn it is.
+}
+
+impl Tracker {
+    /// Create a tracker from entrants and their initiative. Entrants with
+    /// equal initiative retain their order.
+    pub fn new(mut entrants: Vec<(String, i64)>) -> Self {
+        entrants.sort_by_key(|&(_, initiative)| Reverse(initiative));
+        Self { entrants, turn: 0 }
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.entrants.get(self.turn).map(|(name, _)| name.as_str())
+    }
+
+    /// Advance to the next entrant, returning to the top of the order after
+    /// the last.
+    pub fn next(&mut self) -> Option<&str> {
+        if !self.entrants.is_empty() {
+            self.turn = (self.turn + 1) % self.entrants.len();
+        }
+        self.current()
+    }
+}
+
+impl Display for Tracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines: Vec<String> = self
+            .entrants
+            .iter()
+            .enumerate()
+            .map(|(i, (name, initiative))| {
+                let marker = if i == self.turn { ">" } else { " " };
+                format!("{marker} {initiative}\t{name}")
+            })
+            .collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tracker(entrants: &[(&str, i64)]) -> Tracker {
+        Tracker::new(
+            entrants
+                .iter()
+                .map(|&(name, initiative)| (name.to_string(), initiative))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_order() {
+        let mut tracker = tracker(&[("goblin", 12), ("fighter", 17), ("wizard", 12)]);
+        assert_eq!(
+            tracker.to_string(),
+            "> 17\tfighter\n  12\tgoblin\n  12\twizard"
+        );
+        assert_eq!(tracker.current(), Some("fighter"));
+        assert_eq!(tracker.next(), Some("goblin"));
+        assert_eq!(tracker.next(), Some("wizard"));
+        assert_eq!(tracker.next(), Some("fighter"));
+        assert_eq!(Tracker::new(Vec::new()).next(), None);
+    }
+}
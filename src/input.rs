@@ -1,5 +1,9 @@
 use std::fmt::Display;
 
+use rustyline::{
+    completion::Completer, highlight::Highlighter, hint::Hinter, validate::Validator, Helper,
+};
+
 pub enum InputError {
     Eof,
     Interrupt,
@@ -16,23 +20,86 @@ impl Display for InputError {
     }
 }
 
+/// Completes identifiers from a list of names.
+struct Completions {
+    names: Vec<String>,
+}
+
+impl Completions {
+    /// Find the start of the identifier ending at pos, and the names which
+    /// begin with that identifier.
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+            .last()
+            .map(|(i, _)| i)
+            .unwrap_or(pos);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() {
+            return (pos, Vec::new());
+        }
+
+        let candidates = self
+            .names
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        (start, candidates)
+    }
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
+
 pub struct Input {
-    editor: rustyline::Editor<(), rustyline::history::MemHistory>,
+    editor: rustyline::Editor<Completions, rustyline::history::MemHistory>,
 }
 
 impl Input {
     const PROMPT: &'static str = "> ";
+    const CONTINUATION_PROMPT: &'static str = ". ";
 
     pub fn new() -> Self {
         // TODO ctrl+d only sends EOF if used on an empty line.
-        let editor = rustyline::Editor::with_history(
+        let mut editor = rustyline::Editor::with_history(
             rustyline::Config::default(),
             rustyline::history::MemHistory::new(),
         )
         .expect("Failed to initialise terminal editor.");
+        editor.set_helper(Some(Completions { names: Vec::new() }));
         Self { editor }
     }
 
+    /// Set the names offered when completing identifiers.
+    pub fn set_completions(&mut self, names: Vec<String>) {
+        if let Some(completions) = self.editor.helper_mut() {
+            completions.names = names;
+        }
+    }
+
     fn readline(&mut self, prompt: &str) -> Result<String, InputError> {
         let line = match self.editor.readline(prompt) {
             Ok(line) => Ok(line),
@@ -56,4 +123,34 @@ impl Input {
     pub fn line(&mut self) -> Result<String, InputError> {
         self.readline(Self::PROMPT)
     }
+
+    pub fn continuation(&mut self) -> Result<String, InputError> {
+        self.readline(Self::CONTINUATION_PROMPT)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_completion_candidates() {
+        let completions = Completions {
+            names: vec!["floor".into(), "flip".into(), "format".into(), "STR".into()],
+        };
+        assert_eq!(
+            completions.candidates("1 + fl", 6),
+            (4, vec!["floor".into(), "flip".into()])
+        );
+        assert_eq!(
+            completions.candidates("f(2) + S", 8),
+            (7, vec!["STR".into()])
+        );
+        assert_eq!(
+            completions.candidates("flo + 1", 3),
+            (0, vec!["floor".into()])
+        );
+        assert_eq!(completions.candidates("fl + ", 5), (5, Vec::new()));
+        assert_eq!(completions.candidates("xyz", 3), (0, Vec::new()));
+    }
 }
@@ -0,0 +1,138 @@
+use crate::{outcome::Outcome, roll::RollOutcome, value::Value};
+
+/// Quote a string, escaping characters which JSON doesn't allow in strings.
+fn string(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Format a decimal, as an integer if it is one. JSON has no representation
+/// of infinities or NaN, so these are null.
+fn number(v: f64) -> String {
+    if !v.is_finite() {
+        "null".to_string()
+    } else if v.fract() == 0.0 && v.abs() < i64::MAX as f64 {
+        (v as i64).to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+fn array<I: IntoIterator<Item = String>>(elements: I) -> String {
+    format!("[{}]", elements.into_iter().collect::<Vec<_>>().join(", "))
+}
+
+fn object<'a, I: IntoIterator<Item = (&'a str, String)>>(fields: I) -> String {
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(key, value)| format!("{}: {value}", string(key)))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+/// Format a value as JSON. Rolled dice are their result and rolls which
+/// haven't been rolled are their notation. Map keys which aren't strings are
+/// formatted as they are displayed.
+pub fn value(value: &Value) -> String {
+    match value {
+        Value::Bool(v) => v.to_string(),
+        Value::Decimal(v) => number(*v),
+        Value::Natural(v) => v.to_string(),
+        Value::Outcome(outcome) => outcome.result.to_string(),
+        Value::Roll(roll) => string(&roll.to_string()),
+        Value::Rolls(rolls) => array(rolls.iter().map(u64::to_string)),
+        Value::List(values) => array(values.iter().map(self::value)),
+        Value::Map(entries) => {
+            let keys: Vec<String> = entries
+                .iter()
+                .map(|(key, _)| match key {
+                    Value::String(s) => s.clone(),
+                    key => key.to_string(),
+                })
+                .collect();
+            object(
+                keys.iter()
+                    .zip(entries)
+                    .map(|(key, (_, v))| (key.as_str(), self::value(v))),
+            )
+        }
+        Value::String(s) => string(s),
+        Value::Function(name) => string(name),
+        Value::Tagged(inner, tag) => object([("value", self::value(inner)), ("tag", string(tag))]),
+        Value::Empty => "null".to_string(),
+    }
+}
+
+fn roll_outcome(outcome: &RollOutcome) -> String {
+    object([
+        ("roll", string(&outcome.roll.to_string())),
+        ("rolls", array(outcome.rolls.iter().map(u64::to_string))),
+        ("result", outcome.result.to_string()),
+    ])
+}
+
+/// Format an outcome as a JSON object of its value and the rolls made.
+pub fn outcome(outcome: &Outcome) -> String {
+    object([
+        ("value", value(&outcome.value)),
+        ("rolls", array(outcome.rolls.iter().map(roll_outcome))),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::roll::Roll;
+
+    #[test]
+    fn test_value() {
+        assert_eq!(value(&Value::Decimal(8.0)), "8");
+        assert_eq!(value(&Value::Decimal(0.55)), "0.55");
+        assert_eq!(value(&Value::Decimal(f64::NAN)), "null");
+        assert_eq!(
+            value(&Value::String("a \"b\"\n".into())),
+            "\"a \\\"b\\\"\\n\""
+        );
+        assert_eq!(
+            value(&Value::List(vec![Value::Bool(true), Value::Empty])),
+            "[true, null]"
+        );
+        assert_eq!(
+            value(&Value::Map(vec![
+                (Value::String("str".into()), Value::Natural(16)),
+                (Value::Natural(1), Value::Roll(Roll::new(2, 6))),
+            ])),
+            "{\"str\": 16, \"1\": \"2d6\"}"
+        );
+        assert_eq!(
+            value(&Value::Natural(3).tagged("fire".into())),
+            "{\"value\": 3, \"tag\": \"fire\"}"
+        );
+    }
+
+    #[test]
+    fn test_outcome() {
+        let mut outcome = Outcome::nat(9);
+        outcome.rolls.push(RollOutcome {
+            roll: Roll::new(2, 6),
+            rolls: vec![3, 6],
+            result: 9,
+        });
+        assert_eq!(
+            self::outcome(&outcome),
+            "{\"value\": 9, \"rolls\": [{\"roll\": \"2d6\", \"rolls\": [3, 6], \"result\": 9}]}"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 #![feature(if_let_guard)]
 #![feature(let_chains)]
 
+use std::io::{BufRead, IsTerminal};
+
 use context::Context;
 use eval::evaluate_tome;
 
@@ -8,14 +10,20 @@ mod ast;
 mod builtins;
 mod commands;
 mod context;
+mod currency;
+mod distribution;
 mod eval;
+mod initiative;
 mod input;
+mod json;
 mod load;
 mod operator;
 mod outcome;
 mod parser;
+mod rng;
 mod roll;
 mod token;
+mod transcript;
 mod value;
 
 type Res<T> = Result<T, String>;
@@ -25,9 +33,22 @@ struct AppState {
     context: context::Context,
     interrupted: bool,
     cache: context::Context,
+    last: Option<String>,
+    initiative: Option<initiative::Tracker>,
+    transcript: Option<transcript::Transcript>,
+    color: bool, // Whether to colour critical rolls, if output is a terminal.
 }
 
 const CACHE_TITLE: &str = "_cache";
+const REPEAT: &str = "!!";
+const USAGE: &str = "Usage: spells [--json] [expression]";
+
+/// Options given on the command line.
+#[derive(Debug, Default, PartialEq)]
+struct Args {
+    json: bool,                 // Print the outcome as JSON.
+    expression: Option<String>, // Expression to evaluate instead of running the REPL.
+}
 
 fn err<T, S: ToString>(msg: S) -> Res<T> {
     Err(msg.to_string())
@@ -37,8 +58,16 @@ fn parse(input: &str) -> Res<ast::Ast> {
     parser::parse(&token::tokenise(input)?)
 }
 
+#[cfg(test)]
 fn eval(input: &str, context: &mut context::Context) -> Res<outcome::Outcome> {
-    eval::evaluate(&parse(input)?, context, Context::GLOBAL_SCOPE).and_then(|oc| oc.resolved())
+    eval_ast(&parse(input)?, context)
+}
+
+fn eval_ast(ast: &ast::Ast, context: &mut context::Context) -> Res<outcome::Outcome> {
+    let outcome =
+        eval::evaluate(ast, context, Context::GLOBAL_SCOPE).and_then(|oc| oc.resolved())?;
+    context.record_rolls(outcome.roll_details());
+    Ok(outcome)
 }
 
 fn eval_tome(input: &str, context: &mut context::Context) -> Res<()> {
@@ -47,10 +76,150 @@ fn eval_tome(input: &str, context: &mut context::Context) -> Res<()> {
     evaluate_tome(&statements, context, Context::GLOBAL_SCOPE)
 }
 
-fn interpret(input: &str, context: &mut context::Context) {
-    match eval(input, context) {
-        Ok(outcome) => println!("{outcome}"),
-        Err(e) => println!("{e}"),
+/// Evaluate each statement of the input in turn, printing the outcome of
+/// each. Stops at the first error, which is returned without being printed,
+/// along with the lines printed, without colour.
+fn interpret(input: &str, context: &mut context::Context, color: bool) -> (Vec<String>, Res<()>) {
+    let mut output = Vec::new();
+    // Print a line, or a coloured version of it, and record the line.
+    let mut emit = |line: String, colored: Option<String>| {
+        println!("{}", colored.as_ref().unwrap_or(&line));
+        output.push(line);
+    };
+
+    let ast = match parse(input) {
+        Ok(ast) => ast,
+        Err(e) => return (output, Err(e)),
+    };
+    for statement in ast.statements() {
+        let result = eval_ast(&statement, context);
+        for line in context.take_trace() {
+            emit(line, None);
+        }
+        match result {
+            Ok(outcome) => {
+                let text = outcome.format(context.precision(), false);
+                if !text.is_empty() {
+                    let colored = color.then(|| outcome.format(context.precision(), true));
+                    emit(text, colored);
+                }
+            }
+            Err(e) => return (output, Err(e)),
+        }
+    }
+    (output, Ok(()))
+}
+
+/// Append an input and its output to the session transcript, if logging.
+/// Logging stops if the transcript can't be written.
+fn record(state: &mut AppState, input: &str, output: &[String]) {
+    if let Some(transcript) = &mut state.transcript
+        && let Err(e) = transcript.record(input, output)
+    {
+        println!("{e}");
+        state.transcript = None;
+    }
+}
+
+/// Whether the input is the start of an expression which continues on the
+/// next line.
+fn incomplete(input: &str) -> bool {
+    matches!(parse(input), Err(e) if e == parser::INCOMPLETE)
+}
+
+/// Read continuation lines until the input is a complete expression or there
+/// are no more lines.
+fn complete<F: FnMut() -> Option<String>>(mut text: String, mut next_line: F) -> String {
+    while text.trim() != REPEAT && incomplete(&text) {
+        match next_line() {
+            Some(line) => {
+                text.push('\n');
+                text.push_str(&line);
+            }
+            None => break,
+        }
+    }
+    text
+}
+
+/// Determine the expression to interpret for a line of input, substituting
+/// the last expression for the repeat token.
+fn expression(last: &mut Option<String>, text: String) -> Option<String> {
+    if text.trim() == REPEAT {
+        last.clone()
+    } else {
+        *last = Some(text.clone());
+        Some(text)
+    }
+}
+
+/// Parse command line arguments, excluding the program name. Arguments which
+/// aren't flags are joined to form the expression.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Res<Args> {
+    let mut parsed = Args::default();
+    let mut words = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => parsed.json = true,
+            _ if arg.starts_with("--") => return err(USAGE),
+            _ => words.push(arg),
+        }
+    }
+
+    if !words.is_empty() {
+        parsed.expression = Some(words.join(" "));
+    } else if parsed.json {
+        return err("--json requires an expression.");
+    }
+    Ok(parsed)
+}
+
+/// Evaluate an expression given on the command line and print its outcome,
+/// as JSON if requested. Errors are printed to stderr. Returns whether
+/// evaluation succeeded.
+fn evaluate_once(expression: &str, json: bool) -> bool {
+    let mut context = Context::default();
+    let result = if json {
+        parse(expression)
+            .and_then(|ast| eval_ast(&ast, &mut context))
+            .map(|outcome| println!("{}", json::outcome(&outcome)))
+    } else {
+        interpret(expression, &mut context, std::io::stdout().is_terminal()).1
+    };
+
+    if let Err(e) = &result {
+        eprintln!("{e}");
+    }
+    result.is_ok()
+}
+
+/// Handle a line of input, running it if it is a command or otherwise
+/// interpreting it as an expression, reading continuation lines from
+/// next_line if it is incomplete.
+fn handle_line<F>(state: &mut AppState, text: String, mut next_line: F)
+where
+    F: FnMut(&mut AppState) -> Option<String>,
+{
+    if text.trim().is_empty() {
+        // ignore empty lines
+    } else if text.starts_with('.') {
+        if let Err(e) = commands::handle(&text, state) {
+            println!("{e}");
+        }
+    } else {
+        let text = complete(text, || next_line(state));
+        match expression(&mut state.last, text) {
+            Some(input) => {
+                let color = state.color && std::io::stdout().is_terminal();
+                let (mut output, result) = interpret(&input, &mut state.context, color);
+                if let Err(e) = result {
+                    println!("{e}");
+                    output.push(e);
+                }
+                record(state, &input, &output);
+            }
+            None => println!("No previous expression to repeat."),
+        }
     }
 }
 
@@ -69,30 +238,47 @@ fn load_cache(state: &mut AppState) -> Res<()> {
 }
 
 fn main() {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    if let Some(expression) = &args.expression {
+        let success = evaluate_once(expression, args.json);
+        std::process::exit(if success { 0 } else { 1 });
+    }
+
     let mut state = AppState {
         input: input::Input::new(),
         context: context::Context::default(),
         interrupted: false,
         cache: context::Context::empty(),
+        last: None,
+        initiative: None,
+        transcript: None,
+        color: true,
     };
 
+    // Input piped to stdin is read line by line without the line editor,
+    // and without loading or saving the last tome.
+    if !std::io::stdin().is_terminal() {
+        let mut lines = std::io::stdin().lock().lines();
+        while let Some(Ok(text)) = lines.next() {
+            handle_line(&mut state, text, |_| lines.next()?.ok());
+        }
+        return;
+    }
+
     if let Err(e) = load_cache(&mut state) {
         println!("{e}");
     }
 
     loop {
+        state.input.set_completions(state.context.names());
         match state.input.line() {
-            Ok(text) => {
-                if text.trim().is_empty() {
-                    // ignore empty lines
-                } else if text.starts_with('.') {
-                    if let Err(e) = commands::handle(&text, &mut state) {
-                        println!("{e}");
-                    }
-                } else {
-                    interpret(&text, &mut state.context);
-                }
-            }
+            Ok(text) => handle_line(&mut state, text, |state| state.input.continuation().ok()),
             Err(input::InputError::Interrupt) => {
                 if state.interrupted {
                     std::process::exit(0);
@@ -108,3 +294,80 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_complete() {
+        let mut lines = vec!["1 else 2"];
+        let text = complete("if false then".into(), || lines.pop().map(String::from));
+        assert_eq!(text, "if false then\n1 else 2");
+        assert!(lines.is_empty());
+        assert_eq!(
+            eval(&text, &mut Context::empty()).unwrap().value,
+            value::Value::Natural(2)
+        );
+
+        let mut lines = vec!["3"];
+        assert_eq!(
+            complete("1 + 2".into(), || lines.pop().map(String::from)),
+            "1 + 2"
+        );
+        assert_eq!(complete("[1,".into(), || None), "[1,");
+        assert_eq!(complete(REPEAT.into(), || panic!()), REPEAT);
+        assert!(!incomplete("1 +* 2"));
+    }
+
+    #[test]
+    fn test_statements() {
+        let context = &mut Context::empty();
+        let statements = parse("d20 + 5; d20 + 5").unwrap().statements();
+        assert_eq!(statements.len(), 2);
+        for statement in &statements {
+            assert_eq!(statement.render(), "d20 + 5");
+            assert_eq!(eval_ast(statement, context).unwrap().rolls.len(), 1);
+        }
+
+        let statements = parse("x = 1; y = x + 1; y * 2").unwrap().statements();
+        let outcomes: Vec<_> = statements
+            .iter()
+            .map(|s| eval_ast(s, context).unwrap().value.natural())
+            .collect();
+        assert_eq!(outcomes, vec![Ok(1), Ok(2), Ok(4)]);
+
+        assert_eq!(parse("f() := a; b").unwrap().statements().len(), 1);
+        assert_eq!(parse("[1; 2, 3]").unwrap().statements().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|s| s.to_string()));
+        assert_eq!(args(&[]), Ok(Args::default()));
+        assert_eq!(
+            args(&["--json", "2d6", "+", "3"]),
+            Ok(Args {
+                json: true,
+                expression: Some("2d6 + 3".into())
+            })
+        );
+        assert_eq!(args(&["-1"]).unwrap().expression, Some("-1".into()));
+        assert!(args(&["--json"]).is_err());
+        assert!(args(&["--jsno", "2d6"]).is_err());
+    }
+
+    #[test]
+    fn test_repeat_expression() {
+        let mut last = None;
+        assert_eq!(expression(&mut last, REPEAT.into()), None);
+        assert_eq!(
+            expression(&mut last, "d20 + 2".into()),
+            Some("d20 + 2".into())
+        );
+        assert_eq!(expression(&mut last, REPEAT.into()), Some("d20 + 2".into()));
+        assert_eq!(expression(&mut last, " !! ".into()), Some("d20 + 2".into()));
+        assert_eq!(expression(&mut last, "d6".into()), Some("d6".into()));
+        assert_eq!(expression(&mut last, REPEAT.into()), Some("d6".into()));
+    }
+}
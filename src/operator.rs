@@ -8,11 +8,19 @@ pub enum Operator {
     Sub,
     Mul,
     Div,
+    FloorDiv,
+    Mod,
     Exp,
+    Range,
+    RangeInclusive,
     Neg,
     Keep,
     Adv,
     DisAdv,
+    Explode,
+    Compound,
+    ExplodeAt,
+    CompoundAt,
     Equal,
     GreaterThan,
     LessThan,
@@ -21,32 +29,46 @@ pub enum Operator {
     And,
     Or,
     Not,
+    Coalesce,
 }
 
 impl Operator {
     // Operators which are produced context-free by the tokeniser.
     // NB it is important that these are ordered longest-to-shortest.
     pub const TOKENS: &'static [Operator] = &[
-        Operator::Define,       // :=
-        Operator::Equal,        // ==
-        Operator::GreaterEqual, // >=
-        Operator::LessEqual,    // <=
-        Operator::GreaterThan,  // >
-        Operator::LessThan,     // <
-        Operator::Assign,       // =
-        Operator::Discard,      // ;
-        Operator::Add,          // +
-        Operator::Sub,          // -
-        Operator::Mul,          // *
-        Operator::Div,          // /
-        Operator::Exp,          // ^
-        Operator::And,          // &
-        Operator::Or,           // |
-        Operator::Not,          // !
+        Operator::RangeInclusive, // ..=
+        Operator::Define,         // :=
+        Operator::Equal,          // ==
+        Operator::GreaterEqual,   // >=
+        Operator::LessEqual,      // <=
+        Operator::Coalesce,       // ??
+        Operator::FloorDiv,       // //
+        Operator::Range,          // ..
+        Operator::GreaterThan,    // >
+        Operator::LessThan,       // <
+        Operator::Assign,         // =
+        Operator::Discard,        // ;
+        Operator::Add,            // +
+        Operator::Sub,            // -
+        Operator::Mul,            // *
+        Operator::Div,            // /
+        Operator::Mod,            // %
+        Operator::Exp,            // ^
+        Operator::And,            // &
+        Operator::Or,             // |
+        Operator::Not,            // !
     ];
 
     pub const ROLL_SUFFIX_TOKENS: &'static [Operator] = &[Self::Keep, Self::Adv, Self::DisAdv];
 
+    // Operators which may follow a roll to make it explode, longest first.
+    pub const EXPLODE_TOKENS: &'static [Operator] = &[
+        Self::CompoundAt, // !!>=
+        Self::ExplodeAt,  // !>=
+        Self::Compound,   // !!
+        Self::Explode,    // !
+    ];
+
     pub fn precedence(&self) -> u8 {
         match self {
             Operator::Sentinel => 0,
@@ -55,25 +77,34 @@ impl Operator {
             Operator::Assign => 3,
             Operator::And => 4,
             Operator::Or => 4,
+            Operator::Coalesce => 4,
             Operator::GreaterThan => 5,
             Operator::LessThan => 5,
             Operator::GreaterEqual => 5,
             Operator::LessEqual => 5,
             Operator::Equal => 5,
-            Operator::Add => 6,
-            Operator::Sub => 6,
-            Operator::Mul => 7,
-            Operator::Div => 7,
-            Operator::Not => 8,
-            Operator::Neg => 8,
-            Operator::Adv => 8,
-            Operator::DisAdv => 8,
-            Operator::Exp => 9,
-            Operator::Keep => 10,
+            Operator::Range => 6,
+            Operator::RangeInclusive => 6,
+            Operator::Add => 7,
+            Operator::Sub => 7,
+            Operator::Mul => 8,
+            Operator::Div => 8,
+            Operator::FloorDiv => 8,
+            Operator::Mod => 8,
+            Operator::Not => 9,
+            Operator::Neg => 9,
+            Operator::Adv => 9,
+            Operator::DisAdv => 9,
+            Operator::Explode => 9,
+            Operator::Compound => 9,
+            Operator::Exp => 10,
+            Operator::Keep => 11,
+            Operator::ExplodeAt => 11,
+            Operator::CompoundAt => 11,
         }
     }
 
-    fn left_associative(&self) -> bool {
+    pub fn left_associative(&self) -> bool {
         match self {
             Operator::Sentinel => false,
             Operator::Assign => false,
@@ -81,16 +112,25 @@ impl Operator {
             Operator::Discard => true,
             Operator::And => true,
             Operator::Or => true,
+            Operator::Coalesce => false,
             Operator::Not => false,
             Operator::Add => true,
             Operator::Sub => true,
             Operator::Mul => true,
             Operator::Div => true,
+            Operator::FloorDiv => true,
+            Operator::Mod => true,
             Operator::Exp => false,
+            Operator::Range => true,
+            Operator::RangeInclusive => true,
             Operator::Neg => false,
             Operator::Keep => true,
             Operator::Adv => false,
             Operator::DisAdv => false,
+            Operator::Explode => false,
+            Operator::Compound => false,
+            Operator::ExplodeAt => true,
+            Operator::CompoundAt => true,
             Operator::Equal => true,
             Operator::GreaterThan => true,
             Operator::LessThan => true,
@@ -107,16 +147,25 @@ impl Operator {
             Operator::Discard => true,
             Operator::And => true,
             Operator::Or => true,
+            Operator::Coalesce => true,
             Operator::Not => false,
             Operator::Add => true,
             Operator::Sub => true,
             Operator::Mul => true,
             Operator::Div => true,
+            Operator::FloorDiv => true,
+            Operator::Mod => true,
             Operator::Exp => true,
+            Operator::Range => true,
+            Operator::RangeInclusive => true,
             Operator::Neg => false,
             Operator::Keep => true,
             Operator::Adv => false,
             Operator::DisAdv => false,
+            Operator::Explode => false,
+            Operator::Compound => false,
+            Operator::ExplodeAt => true,
+            Operator::CompoundAt => true,
             Operator::Equal => true,
             Operator::GreaterThan => true,
             Operator::LessThan => true,
@@ -128,7 +177,12 @@ impl Operator {
     pub fn is_unary(&self) -> bool {
         matches!(
             self,
-            Operator::Not | Operator::Neg | Operator::Adv | Operator::DisAdv
+            Operator::Not
+                | Operator::Neg
+                | Operator::Adv
+                | Operator::DisAdv
+                | Operator::Explode
+                | Operator::Compound
         )
     }
 
@@ -137,7 +191,10 @@ impl Operator {
     }
 
     pub fn is_unary_postfix(&self) -> bool {
-        matches!(self, Operator::Adv | Operator::DisAdv)
+        matches!(
+            self,
+            Operator::Adv | Operator::DisAdv | Operator::Explode | Operator::Compound
+        )
     }
 
     pub fn greater(left: &Self, right: &Self) -> bool {
@@ -160,15 +217,24 @@ impl Operator {
             Operator::And => &['&'],
             Operator::Or => &['|'],
             Operator::Not => &['!'],
+            Operator::Coalesce => &['?', '?'],
             Operator::Add => &['+'],
             Operator::Sub => &['-'],
             Operator::Mul => &['*'],
             Operator::Div => &['/'],
+            Operator::FloorDiv => &['/', '/'],
+            Operator::Mod => &['%'],
             Operator::Exp => &['^'],
+            Operator::Range => &['.', '.'],
+            Operator::RangeInclusive => &['.', '.', '='],
             Operator::Neg => &['-'],
             Operator::Keep => &['k'],
             Operator::Adv => &['a'],
             Operator::DisAdv => &['d'],
+            Operator::Explode => &['!'],
+            Operator::Compound => &['!', '!'],
+            Operator::ExplodeAt => &['!', '>', '='],
+            Operator::CompoundAt => &['!', '!', '>', '='],
             Operator::Equal => &['=', '='],
             Operator::GreaterThan => &['>'],
             Operator::LessThan => &['<'],
@@ -1,11 +1,41 @@
-use std::fmt::{Display, Write};
+use std::fmt::Display;
+
+use crate::{
+    err,
+    roll::{Explode, RollOutcome},
+    value::{format_value, Value, DEFAULT_PRECISION},
+    Res,
+};
+
+// ANSI escape sequence resetting the colour of text.
+const RESET: &str = "\x1b[0m";
+
+/// Tag the result of arithmetic with the damage type of its operands, if only
+/// one type is present.
+fn retag(result: Value, lhs: &Value, rhs: &Value) -> Value {
+    match (lhs.tag(), rhs.tag()) {
+        (Some(a), Some(b)) if a != b => result,
+        (Some(tag), _) | (None, Some(tag)) => result.tagged(tag.to_string()),
+        (None, None) => result,
+    }
+}
 
-use crate::{roll::RollOutcome, value::Value, Res};
+/// Floored modulo, which has the sign of the divisor, unlike the truncated
+/// remainder of the % operator in Rust.
+fn modulo(lhs: f64, rhs: f64) -> f64 {
+    let rem = lhs % rhs;
+    if rem != 0.0 && (rem < 0.0) != (rhs < 0.0) {
+        rem + rhs
+    } else {
+        rem
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Outcome {
     pub value: Value,
     pub rolls: Vec<RollOutcome>,
+    pub label: Option<String>, // Description shown before the outcome.
 }
 
 impl Outcome {
@@ -13,6 +43,7 @@ impl Outcome {
         Self {
             value,
             rolls: Vec::new(),
+            label: None,
         }
     }
 
@@ -42,13 +73,18 @@ impl Outcome {
         self.resolve_for(Value::bool)
     }
 
-    fn arithmetic<F: Fn(f64, f64) -> f64>(self, other: Outcome, f: F) -> Res<Outcome> {
+    fn arithmetic<F: Fn(f64, f64) -> Res<f64>>(self, other: Outcome, f: F) -> Res<Outcome> {
         let (mut this, lhs) = self.decimal()?;
         let (mut that, rhs) = other.decimal()?;
+        let value = f(lhs, rhs)?;
+        if !value.is_finite() {
+            return err("Result is too large or not a number.");
+        }
         this.rolls.append(&mut that.rolls);
         Ok(Outcome {
-            value: Value::Decimal(f(lhs, rhs)),
+            value: retag(Value::Decimal(value), &this.value, &that.value),
             rolls: this.rolls,
+            label: None,
         })
     }
 
@@ -59,6 +95,7 @@ impl Outcome {
         Ok(Outcome {
             value: Value::Bool(f(lhs, rhs)),
             rolls: this.rolls,
+            label: None,
         })
     }
 
@@ -69,6 +106,7 @@ impl Outcome {
         Ok(Outcome {
             value: Value::Bool(f(lhs, rhs)),
             rolls: this.rolls,
+            label: None,
         })
     }
 
@@ -81,42 +119,93 @@ impl Outcome {
             Ok(Outcome {
                 value: Value::String(format!("{lhs}{rhs}")),
                 rolls: self.rolls,
+                label: None,
             })
         } else {
-            self.arithmetic(other, |lhs, rhs| lhs + rhs)
+            self.arithmetic(other, |lhs, rhs| Ok(lhs + rhs))
         }
     }
 
     pub fn sub(self, other: Outcome) -> Res<Outcome> {
-        self.arithmetic(other, |lhs, rhs| lhs - rhs)
+        self.arithmetic(other, |lhs, rhs| Ok(lhs - rhs))
     }
 
     pub fn mul(self, other: Outcome) -> Res<Outcome> {
-        self.arithmetic(other, |lhs, rhs| lhs * rhs)
+        self.arithmetic(other, |lhs, rhs| Ok(lhs * rhs))
     }
 
     pub fn div(self, other: Outcome) -> Res<Outcome> {
-        self.arithmetic(other, |lhs, rhs| lhs / rhs)
+        self.arithmetic(other, |lhs, rhs| {
+            if rhs == 0.0 {
+                err("Division by zero.")
+            } else {
+                Ok(lhs / rhs)
+            }
+        })
+    }
+
+    /// Quotient rounded down, towards negative infinity, so that
+    /// `-7 // 2 == -4`.
+    pub fn floor_div(self, other: Outcome) -> Res<Outcome> {
+        self.arithmetic(other, |lhs, rhs| {
+            if rhs == 0.0 {
+                err("Division by zero.")
+            } else {
+                Ok(((lhs - modulo(lhs, rhs)) / rhs).round())
+            }
+        })
+    }
+
+    /// Remainder of floor division, which has the sign of the divisor, so that
+    /// `a == b * (a // b) + a % b`.
+    pub fn modulo(self, other: Outcome) -> Res<Outcome> {
+        self.arithmetic(other, |lhs, rhs| {
+            if rhs == 0.0 {
+                err("Division by zero.")
+            } else {
+                Ok(modulo(lhs, rhs))
+            }
+        })
     }
 
     pub fn exp(self, other: Outcome) -> Res<Outcome> {
-        self.arithmetic(other, |lhs, rhs| lhs.powf(rhs))
+        self.arithmetic(other, |lhs, rhs| Ok(lhs.powf(rhs)))
+    }
+
+    /// Half-open range of naturals from self to other. If inclusive, other is
+    /// included in the range.
+    pub fn range(self, other: Outcome, inclusive: bool) -> Res<Outcome> {
+        let (mut this, start) = self.natural()?;
+        let (mut that, end) = other.natural()?;
+        this.rolls.append(&mut that.rolls);
+        let end = if inclusive {
+            end.checked_add(1).ok_or("Numeric overflow.")?
+        } else {
+            end
+        };
+        Ok(Outcome {
+            value: Value::range(start, end)?,
+            rolls: this.rolls,
+            label: None,
+        })
     }
 
     pub fn neg(self) -> Res<Outcome> {
         let (this, value) = self.decimal()?;
         Ok(Self {
-            value: Value::Decimal(-value),
+            value: retag(Value::Decimal(-value), &this.value, &Value::Empty),
             rolls: this.rolls,
+            label: this.label,
         })
     }
 
     pub fn adv(self) -> Res<Outcome> {
         let mut roll = self.value.roll()?;
-        roll.advantage = true;
+        roll.advantage = roll.advantage.saturating_add(1);
         Ok(Self {
             value: Value::Roll(roll),
             rolls: self.rolls,
+            label: self.label,
         })
     }
 
@@ -126,6 +215,35 @@ impl Outcome {
         Ok(Self {
             value: Value::Roll(roll),
             rolls: self.rolls,
+            label: self.label,
+        })
+    }
+
+    pub fn explode(self, explode: Explode) -> Res<Self> {
+        let mut roll = self.value.roll()?;
+        roll.explode = Some(explode);
+        Ok(Self {
+            value: Value::Roll(roll),
+            rolls: self.rolls,
+            label: self.label,
+        })
+    }
+
+    pub fn explode_at(self, explode: Explode, rhs: Self) -> Res<Self> {
+        let (mut that, threshold) = rhs.natural()?;
+        if threshold < 2 {
+            return err("Explosion threshold must be at least 2.");
+        }
+
+        let mut roll = self.value.roll()?;
+        roll.explode = Some(explode);
+        roll.threshold = Some(threshold as u64);
+        let mut rolls = self.rolls;
+        rolls.append(&mut that.rolls);
+        Ok(Self {
+            value: Value::Roll(roll),
+            rolls,
+            label: self.label,
         })
     }
 
@@ -133,32 +251,32 @@ impl Outcome {
         let (mut this, mut values) = self.rolls()?;
         let (mut that, keep) = rhs.natural()?;
         this.rolls.append(&mut that.rolls);
+        if keep < 0 {
+            return err("Cannot keep a negative number of dice.");
+        }
 
+        // Drop the lowest dice, the earliest first among equals, keeping the
+        // rest in the order they were rolled. Keeping 0 drops every die.
         let keep = keep as usize;
         if keep < values.len() {
-            let mut to_remove = values.len() - keep;
-            let mut smallest = None;
-            while to_remove > 0 {
-                for (i, v) in values.iter().enumerate() {
-                    if smallest.is_none() {
-                        smallest = Some((i, *v));
-                    } else if let Some((_, sv)) = smallest
-                        && sv > *v
-                    {
-                        smallest = Some((i, *v));
-                    }
-                }
-
-                if let Some((i, _)) = smallest {
-                    values.remove(i);
-                }
-                to_remove -= 1;
+            let mut order: Vec<usize> = (0..values.len()).collect();
+            order.sort_by_key(|&i| values[i]);
+            let mut dropped = vec![false; values.len()];
+            for &i in &order[..values.len() - keep] {
+                dropped[i] = true;
             }
+            values = values
+                .into_iter()
+                .zip(dropped)
+                .filter(|&(_, dropped)| !dropped)
+                .map(|(v, _)| v)
+                .collect();
         }
 
         Ok(Self {
             value: Value::Rolls(values),
             rolls: this.rolls,
+            label: None,
         })
     }
 
@@ -183,6 +301,7 @@ impl Outcome {
         Ok(Self {
             value: Value::Bool(self.value == other.value),
             rolls: self.rolls,
+            label: None,
         })
     }
 
@@ -207,6 +326,16 @@ impl Outcome {
         Self::new(Value::Empty)
     }
 
+    pub fn labelled(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Rolls made in evaluating this outcome, in the order they were made.
+    pub fn roll_details(&self) -> &[RollOutcome] {
+        &self.rolls
+    }
+
     pub fn resolved(self) -> Res<Self> {
         if matches!(self.value, Value::Roll(_)) {
             self.natural().map(|oc| oc.0)
@@ -216,17 +345,31 @@ impl Outcome {
     }
 }
 
-impl Display for Outcome {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Outcome {
+    /// Format the rolls and value of this outcome, with decimals rounded to
+    /// the given number of places. If color is set, critical rolls are
+    /// coloured with ANSI escape sequences.
+    pub fn format(&self, precision: usize, color: bool) -> String {
+        let mut ret = String::new();
+        if let Some(label) = &self.label {
+            ret += &format!("{label}:\n");
+        }
         for roll in &self.rolls {
-            roll.fmt(f)?;
-            f.write_char('\n')?;
+            match roll.critical() {
+                Some(critical) if color => ret += &format!("{}{roll}{RESET}\n", critical.color()),
+                _ => ret += &format!("{roll}\n"),
+            }
         }
 
-        if matches!(self.value, Value::Empty) {
-            std::fmt::Result::Ok(())
-        } else {
-            write!(f, "{}", self.value)
+        if !matches!(self.value, Value::Empty) {
+            ret += &format_value(&self.value, precision);
         }
+        ret
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format(DEFAULT_PRECISION, false))
     }
 }
@@ -95,10 +95,13 @@ impl<'a> Parser<'a> {
 
     fn term(&mut self) -> Res<usize> {
         let token = self.next()?.clone();
-        let id = match token.inner() {
+        let mut id = match token.inner() {
             Tok::Identifier(name) => match name.as_str() {
                 "if" => self.conditional(),
-                "then" | "else" => {
+                "match" => self.matching(),
+                "try" => self.attempt(),
+                "module" => self.module(),
+                "then" | "else" | "elif" => {
                     self.token_err(&token, format!("{name} must follow an opening if."))
                 }
                 "true" => Ok(self.push_operand(Node::Value(Value::Bool(true)))),
@@ -107,6 +110,17 @@ impl<'a> Parser<'a> {
                     let name = self.in_scope(Self::expr)?;
                     Ok(self.push_operand(Node::Import(name)))
                 }
+                "memo" => {
+                    let definition = self.in_scope(Self::expr)?;
+                    if matches!(
+                        self.ast.get(definition),
+                        Some(Node::Binary(_, Operator::Define, _))
+                    ) {
+                        Ok(self.push_operand(Node::Memo(definition)))
+                    } else {
+                        self.token_err(&token, "memo must precede a function definition.")
+                    }
+                }
                 _ => {
                     if self.next_is(Tok::ParenOpen) {
                         self.call(name.clone())
@@ -118,6 +132,7 @@ impl<'a> Parser<'a> {
             Tok::Natural(n) => Ok(self.push_operand(Node::Value(Value::Natural(*n as i64)))),
             Tok::Decimal(v) => Ok(self.push_operand(Node::Value(Value::Decimal(*v)))),
             Tok::Roll(q, d) => Ok(self.push_operand(Node::Value(Value::Roll(Roll::new(*q, *d))))),
+            Tok::DefaultRoll(q) => Ok(self.push_operand(Node::DefaultRoll(*q))),
             Tok::String(val) => Ok(self.push_operand(Node::Value(Value::String(val.clone())))),
             Tok::ParenOpen => {
                 self.operators.push(Operator::Sentinel);
@@ -129,6 +144,10 @@ impl<'a> Parser<'a> {
             Tok::ParenClose => self.token_err(&token, ") unexpected."),
             Tok::BracketOpen => self.list(),
             Tok::BracketClose => self.token_err(&token, "] unexpected."),
+            Tok::BraceOpen => self.braces(),
+            Tok::BraceClose => self.token_err(&token, "} unexpected."),
+            Tok::Arrow => self.token_err(&token, "-> unexpected."),
+            Tok::Colon => self.token_err(&token, ": unexpected."),
             Tok::Comma => self.token_err(&token, ", unexpected."),
             Tok::Operator(op) if op.is_unary_prefix() => {
                 self.push_operator(*op);
@@ -143,6 +162,14 @@ impl<'a> Parser<'a> {
             Tok::Operator(op) => self.token_err(&token, format!("{} unexpected.", op.str())),
         }?;
 
+        while self.next_is(Tok::BracketOpen) {
+            self.next()?; // throw away token
+            let index = self.in_scope(Self::expr)?;
+            self.expect(Tok::BracketClose)?;
+            let target = self.pop_operand()?;
+            id = self.push_operand(Node::Index(target, index));
+        }
+
         while let Some(token) = self.peek()
             && let Tok::Operator(op) = token.inner()
             && op.is_unary_postfix()
@@ -169,12 +196,74 @@ impl<'a> Parser<'a> {
         let fail = if self.next_is(Tok::identifier("else")) {
             self.next()?; // Toss else
             Some(self.in_scope(Self::expr)?)
+        } else if self.next_is(Tok::identifier("elif")) {
+            // elif is sugar for else if.
+            self.next()?; // Toss elif
+            Some(self.in_scope(Self::conditional)?)
         } else {
             None
         };
         Ok(self.push_operand(Node::If(cond, then, fail)))
     }
 
+    fn attempt(&mut self) -> Res<usize> {
+        let expr = self.in_scope(Self::expr)?;
+        self.expect(Tok::identifier("else"))?;
+        let fallback = self.in_scope(Self::expr)?;
+        Ok(self.push_operand(Node::Try(expr, fallback)))
+    }
+
+    fn module(&mut self) -> Res<usize> {
+        let token = self.next()?.clone();
+        let name = match token.inner() {
+            Tok::Identifier(name) if !name.contains('.') => name.clone(),
+            _ => return self.token_err(&token, "module must be followed by a name."),
+        };
+        self.expect(Tok::BraceOpen)?;
+        // Statements follow one another as they do in a tome.
+        let mut body = Vec::new();
+        while !self.next_is(Tok::BraceClose) {
+            body.push(self.in_scope(Self::expr)?);
+        }
+        self.expect(Tok::BraceClose)?;
+        Ok(self.push_operand(Node::Module(name, body)))
+    }
+
+    fn _arm(&mut self) -> Res<(Option<usize>, usize)> {
+        let is_wildcard = self.next_is(Tok::identifier("_"))
+            && matches!(self.input.get(1).map(Token::inner), Some(Tok::Arrow));
+        let pattern = if is_wildcard {
+            self.next()?; // Toss _
+            None
+        } else {
+            Some(self.expr()?)
+        };
+        self.expect(Tok::Arrow)?;
+        let result = self.expr()?;
+        Ok((pattern, result))
+    }
+
+    fn _matching(&mut self) -> Res<Node> {
+        let scrutinee = self.expr()?;
+        self.expect(Tok::BraceOpen)?;
+        let mut arms = Vec::new();
+        while !self.next_is(Tok::BraceClose) {
+            arms.push(self.in_scope(Self::_arm)?);
+            if self.next_is(Tok::Comma) {
+                self.expect(Tok::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.expect(Tok::BraceClose)?;
+        Ok(Node::Match(scrutinee, arms))
+    }
+
+    fn matching(&mut self) -> Res<usize> {
+        let node = self.in_scope(Self::_matching)?;
+        Ok(self.push_operand(node))
+    }
+
     fn _list(&mut self) -> Res<Node> {
         let mut values = Vec::new();
         if !self.next_is(Tok::BracketClose) {
@@ -193,6 +282,47 @@ impl<'a> Parser<'a> {
         Ok(self.push_operand(node))
     }
 
+    fn _entry(&mut self) -> Res<(usize, usize)> {
+        let key = self.expr()?;
+        self.expect(Tok::Colon)?;
+        let value = self.expr()?;
+        Ok((key, value))
+    }
+
+    /// Parse the rest of a map, following its first key.
+    fn _map(&mut self, key: usize) -> Res<Node> {
+        self.expect(Tok::Colon)?;
+        let mut entries = vec![(key, self.in_scope(Self::expr)?)];
+        while self.next_is(Tok::Comma) {
+            self.expect(Tok::Comma)?;
+            entries.push(self.in_scope(Self::_entry)?);
+        }
+        self.expect(Tok::BraceClose)?;
+        Ok(Node::Map(entries))
+    }
+
+    /// Parse a map, or a block of statements if the first expression isn't
+    /// followed by a colon. Empty braces are an empty map.
+    fn _braces(&mut self) -> Res<Node> {
+        if self.next_is(Tok::BraceClose) {
+            self.next()?; // Toss }
+            return Ok(Node::Map(Vec::new()));
+        }
+
+        let first = self.in_scope(Self::expr)?;
+        if self.next_is(Tok::Colon) {
+            self._map(first)
+        } else {
+            self.expect(Tok::BraceClose)?;
+            Ok(Node::Block(self.ast.sequence(first)))
+        }
+    }
+
+    fn braces(&mut self) -> Res<usize> {
+        let node = self.in_scope(Self::_braces)?;
+        Ok(self.push_operand(node))
+    }
+
     fn _call(&mut self, name: String) -> Res<usize> {
         self.expect(Tok::ParenOpen)?;
         let mut args = Vec::new();
@@ -222,7 +352,7 @@ impl<'a> Parser<'a> {
             self.input = &self.input[1..];
             Ok(tok)
         } else {
-            err("Input ended unexpectedly.")
+            err(INCOMPLETE)
         }
     }
 
@@ -298,6 +428,9 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Error produced when the input ends partway through an expression.
+pub const INCOMPLETE: &str = "Input ended unexpectedly.";
+
 pub fn parse(input: &TokenList) -> Res<Ast> {
     Parser::new(input).parse()
 }
@@ -311,9 +444,15 @@ pub fn parse_first(input: &TokenList) -> Res<(Ast, &[Token])> {
 pub fn parse_tome(mut input: TokenList) -> Res<Vec<Ast>> {
     let mut statements = Vec::new();
     while !input.is_empty() {
-        let (ast, rest) = parse_first(&input)?;
+        let (mut ast, rest) = parse_first(&input)?;
+        let consumed = input.len().saturating_sub(rest.len());
+        ast.set_comment(
+            input
+                .comment_after(consumed.saturating_sub(1))
+                .map(str::to_string),
+        );
         statements.push(ast);
-        input.truncate(input.len().saturating_sub(rest.len()));
+        input.truncate(consumed);
     }
     Ok(statements)
 }
@@ -346,6 +485,110 @@ mod test {
         parse(&toks_to_list(toks.into()))
     }
 
+    #[test]
+    fn test_render_multi_character_operators() {
+        for input in [
+            "a >= b := c == d",
+            "a <= b == c",
+            "x := 1 ..= 3",
+            "a > b & c < d | e",
+        ] {
+            let ast = ast_of(input);
+            let rendered = ast.render();
+            assert_eq!(rendered, input);
+            assert_eq!(ast_of(&rendered).exprs(), ast.exprs());
+        }
+    }
+
+    #[test]
+    fn test_render_parentheses() {
+        for input in [
+            "(2 + 3) * 4",
+            "2 - (3 - 4)",
+            "2 - 3 - 4",
+            "(2 ^ 3) ^ 4",
+            "2 ^ 3 ^ 4",
+            "-(2 + 3)",
+            "-2 ^ 3",
+            "(-2) ^ 3",
+            "f(x) := (x + 1) * 2",
+            "(if a then 1 else 2) + 3",
+        ] {
+            let ast = ast_of(input);
+            let rendered = ast.render();
+            assert_eq!(rendered, input);
+            assert_eq!(ast_of(&rendered).exprs(), ast.exprs());
+        }
+    }
+
+    #[test]
+    fn test_render_if() {
+        for input in [
+            "if a then 1",
+            "if a then 1 elif b then 2 else 3",
+            "if a then 1 elif b then 2 elif c then 3",
+            "if a then (if b then 1 else 2) else 3",
+            "if a then (if b then 1) else 2",
+            "if (if a then b else c) then 1",
+            "if a then x = x - 1 ; true else false",
+            "f(x) := if x > 1 then x else 0",
+            "x = (if a then 1 else 2) ; y",
+            "1 + (if a then 1 else 2) * 3",
+            "-(if a then 1 else 2)",
+        ] {
+            let ast = ast_of(input);
+            let rendered = ast.render();
+            assert_eq!(rendered, input);
+            assert_eq!(ast_of(&rendered).exprs(), ast.exprs());
+        }
+    }
+
+    #[test]
+    fn test_render_try() {
+        for input in [
+            "try a / b else 0",
+            "try (try a else b) else c",
+            "try (if a then 1 else 2) else 3",
+            "(try a else 1) + 2",
+            "x = try a else 1",
+        ] {
+            let ast = ast_of(input);
+            let rendered = ast.render();
+            assert_eq!(rendered, input);
+            assert_eq!(ast_of(&rendered).exprs(), ast.exprs());
+        }
+        assert!(parse(&tokenise("try a").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_render_block() {
+        for input in [
+            "{ 1 }",
+            "{ x = 1 ; x + 1 }",
+            "f(x) := { y = x * 2 ; y + 1 }",
+            "{ (if a then 1 else 2) ; 3 }",
+            "{ (g() := 1) ; g() }",
+            "{ a ; { b ; c } } + 1",
+        ] {
+            let ast = ast_of(input);
+            let rendered = ast.render();
+            assert_eq!(rendered, input);
+            assert_eq!(ast_of(&rendered).exprs(), ast.exprs());
+        }
+    }
+
+    #[test]
+    fn test_debug_tree() {
+        assert_eq!(
+            ast_of("-2 ^ 3").debug_tree(),
+            "Unary -\n  Binary ^\n    Value 2\n    Value 3"
+        );
+        assert_eq!(
+            ast_of("if a then f(1) else [2]").debug_tree(),
+            "If\n  Identifier a\n  Call f\n    Value 1\n  List\n    Value 2"
+        );
+    }
+
     #[test]
     fn test_parse_addition() {
         let ast = parse_toks(&[
@@ -569,6 +812,28 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_range() {
+        check_exprs(
+            "1..n + 1",
+            vec![
+                Node::Value(Value::Natural(1)),
+                Node::name("n"),
+                Node::Value(Value::Natural(1)),
+                Node::Binary(1, Operator::Add, 2),
+                Node::Binary(0, Operator::Range, 3),
+            ],
+        );
+        check_exprs(
+            "0..=2",
+            vec![
+                Node::Value(Value::Natural(0)),
+                Node::Value(Value::Natural(2)),
+                Node::Binary(0, Operator::RangeInclusive, 1),
+            ],
+        );
+    }
+
     #[test]
     fn test_variables() {
         check_exprs(
@@ -637,6 +902,21 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_memo() {
+        check_exprs(
+            "memo f(n) := n",
+            vec![
+                Node::name("n"),
+                Node::Call("f".into(), vec![0]),
+                Node::name("n"),
+                Node::Binary(1, Operator::Define, 2),
+                Node::Memo(3),
+            ],
+        );
+        assert!(parse(&tokenise("memo 1 + 2").unwrap()).is_err());
+    }
+
     #[test]
     fn test_call() {
         check_exprs(
@@ -749,6 +1029,77 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_map() {
+        check_exprs(
+            r#"{"str": 16, "stats": {"dex": 14}}"#,
+            vec![
+                Node::Value(Value::String("str".into())),
+                Node::Value(Value::Natural(16)),
+                Node::Value(Value::String("stats".into())),
+                Node::Value(Value::String("dex".into())),
+                Node::Value(Value::Natural(14)),
+                Node::Map(vec![(3, 4)]),
+                Node::Map(vec![(0, 1), (2, 5)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_parse_empty_map() {
+        check_exprs("{}", vec![Node::Map(Vec::new())])
+    }
+
+    #[test]
+    fn test_parse_block() {
+        check_exprs(
+            "{x = 1; x}",
+            vec![
+                Node::name("x"),
+                Node::Value(Value::Natural(1)),
+                Node::Binary(0, Operator::Assign, 1),
+                Node::name("x"),
+                Node::Binary(2, Operator::Discard, 3),
+                Node::Block(vec![2, 3]),
+            ],
+        );
+        assert!(matches!(root(&ast_of("{x}")), Some(Node::Block(_))));
+        assert!(matches!(root(&ast_of("{x: 1}")), Some(Node::Map(_))));
+    }
+
+    #[test]
+    fn test_parse_index() {
+        check_exprs(
+            "-xs[i + 1][0] * 2",
+            vec![
+                Node::name("xs"),
+                Node::name("i"),
+                Node::Value(Value::Natural(1)),
+                Node::Binary(1, Operator::Add, 2),
+                Node::Index(0, 3),
+                Node::Value(Value::Natural(0)),
+                Node::Index(4, 5),
+                Node::Unary(6, Operator::Neg),
+                Node::Value(Value::Natural(2)),
+                Node::Binary(7, Operator::Mul, 8),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_parse_index_assignment() {
+        check_exprs(
+            r#"m["a"] = 1"#,
+            vec![
+                Node::name("m"),
+                Node::Value(Value::String("a".into())),
+                Node::Index(0, 1),
+                Node::Value(Value::Natural(1)),
+                Node::Binary(2, Operator::Assign, 3),
+            ],
+        )
+    }
+
     #[test]
     fn test_parse_if() {
         check_exprs(
@@ -792,6 +1143,27 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_elif() {
+        for (sugared, nested) in [
+            (
+                "if a then 1 elif b then 2 else 3",
+                "if a then 1 else if b then 2 else 3",
+            ),
+            (
+                "if a then 1 elif b then 2 elif c then 3",
+                "if a then 1 else if b then 2 else if c then 3",
+            ),
+            (
+                "if a then 1 elif b then 2",
+                "if a then 1 else (if b then 2)",
+            ),
+        ] {
+            assert_eq!(ast_of(sugared).exprs(), ast_of(nested).exprs());
+        }
+        assert!(parse_toks(&[Tok::identifier("elif")]).is_err());
+    }
+
     #[test]
     fn test_parse_complex_if_condition() {
         check_exprs(
@@ -809,6 +1181,38 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_match() {
+        check_exprs(
+            r#"match x { 1 -> "one", 2 + 1 -> "three", _ -> "other" }"#,
+            vec![
+                Node::name("x"),
+                Node::Value(Value::Natural(1)),
+                Node::Value(Value::String("one".into())),
+                Node::Value(Value::Natural(2)),
+                Node::Value(Value::Natural(1)),
+                Node::Binary(3, Operator::Add, 4),
+                Node::Value(Value::String("three".into())),
+                Node::Value(Value::String("other".into())),
+                Node::Match(0, vec![(Some(1), 2), (Some(5), 6), (None, 7)]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_parse_match_in_expr() {
+        check_exprs(
+            "1 + match y { _ -> 2 }",
+            vec![
+                Node::Value(Value::Natural(1)),
+                Node::name("y"),
+                Node::Value(Value::Natural(2)),
+                Node::Match(1, vec![(None, 2)]),
+                Node::Binary(0, Operator::Add, 3),
+            ],
+        )
+    }
+
     #[test]
     fn test_function_returning_list() {
         check_exprs(
@@ -0,0 +1,24 @@
+use std::cell::RefCell;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Call a function with the random number generator used for rolls and
+/// random builtins.
+pub fn with<T, F: FnOnce(&mut StdRng) -> T>(f: F) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+/// Roll a single die with the given number of sides.
+pub fn roll(die: u64) -> u64 {
+    with(|rng| rng.gen_range(1..=die))
+}
+
+/// Seed the random number generator, making subsequent results reproducible.
+#[cfg(test)]
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
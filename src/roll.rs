@@ -1,11 +1,20 @@
 use std::fmt::Display;
 
+/// How a die which rolls its maximum is rolled again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Explode {
+    Separate, // Each roll is a separate die.
+    Compound, // Each roll is added to the same die.
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Roll {
     pub quantity: u64,
     pub die: u64,
-    pub advantage: bool,
+    pub advantage: u32, // Times advantage applies, each rolling another die.
     pub disadvantage: bool,
+    pub explode: Option<Explode>,
+    pub threshold: Option<u64>, // Least face which explodes, if not the maximum.
 }
 
 impl Roll {
@@ -13,8 +22,34 @@ impl Roll {
         Roll {
             quantity,
             die,
-            advantage: false,
+            advantage: 0,
             disadvantage: false,
+            explode: None,
+            threshold: None,
+        }
+    }
+
+    /// Whether the highest die is taken, as the roll has advantage and not
+    /// disadvantage, which cancel out.
+    pub fn takes_highest(&self) -> bool {
+        self.advantage > 0 && !self.disadvantage
+    }
+
+    /// Whether the lowest die is taken, as the roll has disadvantage and not
+    /// advantage.
+    pub fn takes_lowest(&self) -> bool {
+        self.disadvantage && self.advantage == 0
+    }
+
+    /// Number of dice rolled. With advantage at least one more die than the
+    /// level of advantage is rolled, and with disadvantage at least two.
+    pub fn dice(&self) -> u64 {
+        if self.takes_highest() {
+            self.quantity.max(self.advantage as u64 + 1)
+        } else if self.takes_lowest() {
+            self.quantity.max(2)
+        } else {
+            self.quantity
         }
     }
 }
@@ -29,15 +64,26 @@ impl Display for Roll {
 
         let die = self.die;
 
-        let advstr = if self.advantage && !self.disadvantage {
-            "a"
-        } else if self.disadvantage && !self.advantage {
-            "d"
+        let advstr = if self.takes_highest() {
+            "a".repeat(self.advantage as usize)
+        } else if self.takes_lowest() {
+            "d".to_string()
         } else {
-            ""
+            "".to_string()
         };
 
-        write!(f, "{quantity}d{die}{advstr}")
+        let explodestr = match self.explode {
+            Some(Explode::Separate) => "!",
+            Some(Explode::Compound) => "!!",
+            None => "",
+        };
+
+        let thresholdstr = match self.threshold {
+            Some(threshold) if self.explode.is_some() => format!(">={threshold}"),
+            _ => "".to_string(),
+        };
+
+        write!(f, "{quantity}d{die}{advstr}{explodestr}{thresholdstr}")
     }
 }
 
@@ -48,6 +94,54 @@ pub struct RollOutcome {
     pub result: u64,
 }
 
+/// A natural 20 or natural 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Critical {
+    Success,
+    Failure,
+}
+
+impl Critical {
+    /// ANSI escape sequence setting the colour this critical is shown in.
+    pub fn color(&self) -> &'static str {
+        match self {
+            Critical::Success => "\x1b[32m",
+            Critical::Failure => "\x1b[31m",
+        }
+    }
+}
+
+impl RollOutcome {
+    pub fn roll(&self) -> &Roll {
+        &self.roll
+    }
+
+    /// Faces rolled on each die.
+    pub fn rolls(&self) -> &[u64] {
+        &self.rolls
+    }
+
+    pub fn result(&self) -> u64 {
+        self.result
+    }
+
+    /// Whether the roll was a critical success or failure. Only a roll
+    /// decided by a single d20 can be critical.
+    pub fn critical(&self) -> Option<Critical> {
+        let roll = &self.roll;
+        let single = roll.quantity == 1 || roll.takes_highest() || roll.takes_lowest();
+        if roll.die != 20 || !single || roll.explode.is_some() {
+            return None;
+        }
+
+        match self.result {
+            1 => Some(Critical::Failure),
+            result if result == roll.die => Some(Critical::Success),
+            _ => None,
+        }
+    }
+}
+
 impl Display for RollOutcome {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -63,3 +157,62 @@ impl Display for RollOutcome {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn outcome(roll: Roll, rolls: Vec<u64>, result: u64) -> RollOutcome {
+        RollOutcome {
+            roll,
+            rolls,
+            result,
+        }
+    }
+
+    #[test]
+    fn test_critical() {
+        let d20 = Roll::new(1, 20);
+        assert_eq!(
+            outcome(d20.clone(), vec![20], 20).critical(),
+            Some(Critical::Success)
+        );
+        assert_eq!(
+            outcome(d20.clone(), vec![1], 1).critical(),
+            Some(Critical::Failure)
+        );
+        assert_eq!(outcome(d20.clone(), vec![12], 12).critical(), None);
+
+        // With advantage the kept die decides.
+        let mut adv = d20;
+        adv.advantage = 1;
+        assert_eq!(
+            outcome(adv.clone(), vec![1, 20], 20).critical(),
+            Some(Critical::Success)
+        );
+        assert_eq!(outcome(adv, vec![1, 12], 12).critical(), None);
+
+        assert_eq!(outcome(Roll::new(2, 20), vec![10, 10], 20).critical(), None);
+        assert_eq!(outcome(Roll::new(1, 6), vec![1], 1).critical(), None);
+    }
+
+    #[test]
+    fn test_roll_details() {
+        crate::rng::seed(1);
+        let outcome = crate::eval("2d6 + d20", &mut crate::context::Context::empty()).unwrap();
+        let details = outcome.roll_details();
+        assert_eq!(details.len(), 2);
+
+        let [twod6, d20] = details else { panic!() };
+        assert_eq!(twod6.roll(), &Roll::new(2, 6));
+        assert_eq!(twod6.rolls().len(), 2);
+        assert!(twod6.rolls().iter().all(|face| (1..=6).contains(face)));
+        assert_eq!(twod6.result(), twod6.rolls().iter().sum::<u64>());
+        assert_eq!(d20.roll().die, 20);
+        assert_eq!(d20.rolls(), &[d20.result()]);
+        assert_eq!(
+            outcome.value.clone().natural(),
+            Ok((twod6.result() + d20.result()) as i64)
+        );
+    }
+}
@@ -10,12 +10,17 @@ pub enum Tok {
     Natural(u64),
     Decimal(f64),
     Roll(u64, u64),
+    DefaultRoll(u64), // Quantity of dice of the default size.
     Operator(Operator),
     String(String),
     ParenOpen,
     ParenClose,
     BracketOpen,
     BracketClose,
+    BraceOpen,
+    BraceClose,
+    Arrow,
+    Colon,
     Comma,
 }
 
@@ -53,6 +58,7 @@ impl Token {
 pub struct TokenList {
     text: Vec<char>,
     tokens: Vec<Token>,
+    comments: Vec<(usize, String)>, // Line and text of each comment.
 }
 
 impl TokenList {
@@ -122,11 +128,45 @@ impl TokenList {
         self.range_to_string((token.index)..(token.index + token.len))
     }
 
+    /// Text of the comment on the line of the token at an index, if any.
+    pub fn comment_after(&self, index: usize) -> Option<&str> {
+        let line = self.tokens.get(index)?.line;
+        self.comments
+            .iter()
+            .find(|(l, _)| *l == line)
+            .map(|(_, comment)| comment.as_str())
+    }
+
     pub fn truncate(&mut self, new_start: usize) {
         self.tokens = self.tokens.split_off(new_start);
     }
 }
 
+/// Read the code point of a unicode escape, following the \u. Returns the
+/// length of the escape and the character.
+fn read_unicode_escape(input: &[char]) -> Res<(usize, char)> {
+    const MALFORMED: &str = "Unicode escapes must be of the form \\u{XXXX}.";
+
+    if input.first() != Some(&'{') {
+        return err(MALFORMED);
+    }
+    let digits: String = input[1..]
+        .iter()
+        .take_while(|c| c.is_ascii_hexdigit())
+        .collect();
+    if digits.is_empty() || digits.len() > 6 || input.get(digits.len() + 1) != Some(&'}') {
+        return err(MALFORMED);
+    }
+
+    match u32::from_str_radix(&digits, 16)
+        .ok()
+        .and_then(char::from_u32)
+    {
+        Some(c) => Ok((digits.len() + 2, c)),
+        None => Err(format!("Invalid unicode code point: {digits}.")),
+    }
+}
+
 fn read_string(input: &[char]) -> Res<(usize, Tok)> {
     debug_assert!(input[0] == '"');
 
@@ -135,31 +175,27 @@ fn read_string(input: &[char]) -> Res<(usize, Tok)> {
     let mut i = 1; // Skip opening quote.
     while let Some(c) = input.get(i).copied() {
         i = i + 1;
-        match c {
-            '\\' => {
-                if escaped {
-                    s.push('\\');
-                    escaped = false;
-                } else {
-                    escaped = true;
+        if escaped {
+            escaped = false;
+            match c {
+                '\\' | '"' => s.push(c),
+                'n' => s.push('\n'),
+                't' => s.push('\t'),
+                'r' => s.push('\r'),
+                'u' => {
+                    let (len, c) = read_unicode_escape(&input[i..])?;
+                    s.push(c);
+                    i += len;
                 }
+                '\n' => return err("Strings must be single line."),
+                _ => return Err(format!("Invalid escape sequence: \\{c}")),
             }
-            '"' => {
-                if escaped {
-                    s.push('"');
-                    escaped = false;
-                } else {
-                    return Ok((i, Tok::String(s)));
-                }
-            }
-            'n' if escaped => {
-                s.push('\n');
-                escaped = false;
-            }
-            't' if escaped => {
-                s.push('\t');
-                escaped = false;
-            }
+            continue;
+        }
+
+        match c {
+            '\\' => escaped = true,
+            '"' => return Ok((i, Tok::String(s))),
             '\n' => {
                 return err("Strings must be single line.");
             }
@@ -179,6 +215,7 @@ fn read_number(input: &[char]) -> Res<(usize, Tok)> {
     while let Some(c) = input.get(i).copied() {
         i = i + 1;
         match c {
+            '.' if input.get(i) == Some(&'.') => break, // Range operator.
             '.' => {
                 s.push('.');
                 if is_decimal || is_roll {
@@ -208,6 +245,13 @@ fn read_number(input: &[char]) -> Res<(usize, Tok)> {
         Tok::Decimal(s.parse::<f64>().map_err(|e| e.to_string())?)
     } else if is_roll {
         if let Some((q, d)) = s.split_once('d') {
+            // A bare d is an identifier, but a quantity without a die rolls
+            // the default die.
+            if d.is_empty() && !q.is_empty() {
+                let q = q.parse::<u64>().map_err(|e| e.to_string())?;
+                return Ok((s.len(), Tok::DefaultRoll(q)));
+            }
+
             let q = if q.is_empty() {
                 1
             } else {
@@ -228,8 +272,13 @@ fn read_identifier(input: &[char]) -> Res<(usize, Tok)> {
     debug_assert!(input[0] == '_' || input[0].is_alphabetic());
 
     let mut s = String::new();
-    for &c in input {
-        if c == '_' || c.is_alphabetic() || (!s.is_empty() && c.is_numeric()) {
+    for (i, &c) in input.iter().enumerate() {
+        // A dot followed by a name qualifies it with a module, as in m.f.
+        let qualifies = c == '.'
+            && input
+                .get(i + 1)
+                .is_some_and(|&c| c == '_' || c.is_alphabetic());
+        if c == '_' || c.is_alphabetic() || (!s.is_empty() && c.is_numeric()) || qualifies {
             s.push(c);
         } else {
             break;
@@ -240,6 +289,10 @@ fn read_identifier(input: &[char]) -> Res<(usize, Tok)> {
 }
 
 fn read_token(input: &[char]) -> Res<(usize, Tok)> {
+    if input.starts_with(&['-', '>']) {
+        return Ok((2, Tok::Arrow));
+    }
+
     for op in Operator::TOKENS {
         if input.starts_with(op.chars()) {
             return Ok((op.chars().len(), Tok::Operator(*op)));
@@ -249,10 +302,13 @@ fn read_token(input: &[char]) -> Res<(usize, Tok)> {
     match input.get(0) {
         None => err("Input ended unexpectedly."),
         Some(',') => Ok((1, Tok::Comma)),
+        Some(':') => Ok((1, Tok::Colon)),
         Some('(') => Ok((1, Tok::ParenOpen)),
         Some(')') => Ok((1, Tok::ParenClose)),
         Some('[') => Ok((1, Tok::BracketOpen)),
         Some(']') => Ok((1, Tok::BracketClose)),
+        Some('{') => Ok((1, Tok::BraceOpen)),
+        Some('}') => Ok((1, Tok::BraceClose)),
         Some('"') => read_string(input),
         Some('.') => read_number(input),
         Some(c) if c.is_numeric() => read_number(input),
@@ -276,13 +332,17 @@ fn read_comment(input: &[char]) -> usize {
 }
 
 fn maybe_read_postfix_roll_op(input: &[char]) -> Res<(usize, Tok)> {
-    let is_operator = if let Some(c) = input.get(1)
-        && !c.is_alphabetic()
-        && *c != '_'
-    {
-        true
+    // A run of advantage and disadvantage suffixes, e.g. d20ad, is read one
+    // operator at a time as long as an identifier doesn't follow.
+    let suffixes = input
+        .iter()
+        .take_while(|c| matches!(c, 'a' | 'd'))
+        .count()
+        .max(1);
+    let is_operator = if let Some(c) = input.get(suffixes) {
+        !c.is_alphabetic() && *c != '_'
     } else {
-        input.len() == 1
+        true
     };
 
     if is_operator && let Some(c) = input.get(0) {
@@ -295,11 +355,27 @@ fn maybe_read_postfix_roll_op(input: &[char]) -> Res<(usize, Tok)> {
     read_token(input)
 }
 
+/// Whether the last token is a roll, or a roll followed by a postfix roll
+/// operator, so that a roll suffix may follow.
+fn follows_roll(tokens: &[Token]) -> bool {
+    matches!(
+        tokens.last().map(Token::inner),
+        Some(
+            Tok::Roll(..)
+                | Tok::DefaultRoll(..)
+                | Tok::Operator(
+                    Operator::Adv | Operator::DisAdv | Operator::Explode | Operator::Compound
+                )
+        )
+    )
+}
+
 pub fn tokenise(input: &str) -> Result<TokenList, String> {
     let text: Vec<char> = input.chars().collect();
 
     let mut input: &[char] = text.as_slice();
     let mut tokens: Vec<Token> = Vec::new();
+    let mut comments = Vec::new();
     let mut index = 0;
     let mut line = 1;
     let mut col = 1;
@@ -321,17 +397,47 @@ pub fn tokenise(input: &str) -> Result<TokenList, String> {
             }
             '#' => {
                 let len = read_comment(input);
+                let comment: String = input[1..len].iter().collect();
+                comments.push((line, comment.trim().to_string()));
                 index += len;
                 line += 1;
                 col = 1;
                 input = &input[len..];
                 whitespace_since_token = true;
             }
-            'a' | 'd' | 'k'
-                if !whitespace_since_token
-                    && let Some(token) = tokens.last()
-                    && let Tok::Roll(..) = token.inner() =>
-            {
+            '!' if !whitespace_since_token && follows_roll(&tokens) => {
+                let op = *Operator::EXPLODE_TOKENS
+                    .iter()
+                    .find(|op| input.starts_with(op.chars()))
+                    .unwrap_or(&Operator::Explode);
+                let len = op.chars().len();
+                tokens.push(Token::new(Tok::Operator(op), line, col, index, len));
+                index += len;
+                col += len;
+                input = &input[len..];
+            }
+            '"' => {
+                let (len, tok) = read_string(input).map_err(|e| {
+                    // Point to the rest of the line from the opening quote.
+                    let len = input.iter().position(|&c| c == '\n');
+                    let len = len.unwrap_or(input.len());
+                    let token = Token::new(Tok::String(String::new()), line, col, index, len);
+                    let text = text.clone();
+                    let context = TokenList {
+                        text,
+                        tokens: Vec::new(),
+                        comments: Vec::new(),
+                    }
+                    .context(&token);
+                    format!("{context}\n{e}")
+                })?;
+                tokens.push(Token::new(tok, line, col, index, len));
+                index += len;
+                col += len;
+                input = &input[len..];
+                whitespace_since_token = false;
+            }
+            'a' | 'd' | 'k' if !whitespace_since_token && follows_roll(&tokens) => {
                 let (len, tok) = maybe_read_postfix_roll_op(input)?;
                 tokens.push(Token::new(tok, line, col, index, len));
                 index += len;
@@ -350,7 +456,11 @@ pub fn tokenise(input: &str) -> Result<TokenList, String> {
         }
     }
 
-    Ok(TokenList { tokens, text })
+    Ok(TokenList {
+        tokens,
+        text,
+        comments,
+    })
 }
 
 #[cfg(test)]
@@ -361,6 +471,7 @@ pub fn toks_to_list(toks: Vec<Tok>) -> TokenList {
             .into_iter()
             .map(|t| Token::new(t.clone(), 0, 0, 0, 0))
             .collect::<Vec<Token>>(),
+        comments: Vec::new(),
     }
 }
 
@@ -385,6 +496,12 @@ mod test {
         assert_eq!(tok_unwrap("d4"), vec![Tok::Roll(1, 4)]);
         assert_eq!(tok_unwrap("8d8"), vec![Tok::Roll(8, 8)]);
         assert_eq!(tok_unwrap("d20"), vec![Tok::Roll(1, 20)]);
+        assert_eq!(tok_unwrap("4d"), vec![Tok::DefaultRoll(4)]);
+        assert_eq!(tok_unwrap("d"), vec![Tok::identifier("d")]);
+        assert_eq!(
+            tok_unwrap("2da"),
+            vec![Tok::DefaultRoll(2), Tok::Operator(Operator::Adv)]
+        );
         assert_eq!(
             tok_unwrap("d20 d20"),
             vec![Tok::Roll(1, 20), Tok::Roll(1, 20)]
@@ -421,6 +538,40 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_tokenise_explode() {
+        assert_eq!(
+            tok_unwrap("d6! 2d6!! 4d6!k3 !d6"),
+            vec![
+                Tok::Roll(1, 6),
+                Tok::Operator(Operator::Explode),
+                Tok::Roll(2, 6),
+                Tok::Operator(Operator::Compound),
+                Tok::Roll(4, 6),
+                Tok::Operator(Operator::Explode),
+                Tok::Operator(Operator::Keep),
+                Tok::Natural(3),
+                Tok::Operator(Operator::Not),
+                Tok::Roll(1, 6),
+            ]
+        );
+        assert_eq!(
+            tok_unwrap("d10!>=8 d10!!>=9 d10! >= 8"),
+            vec![
+                Tok::Roll(1, 10),
+                Tok::Operator(Operator::ExplodeAt),
+                Tok::Natural(8),
+                Tok::Roll(1, 10),
+                Tok::Operator(Operator::CompoundAt),
+                Tok::Natural(9),
+                Tok::Roll(1, 10),
+                Tok::Operator(Operator::Explode),
+                Tok::Operator(Operator::GreaterEqual),
+                Tok::Natural(8),
+            ]
+        );
+    }
+
     #[test]
     fn test_tokenise_roll_suffix() {
         assert_eq!(
@@ -433,7 +584,17 @@ mod test {
                 Tok::Roll(1, 8),
                 Tok::Operator(Operator::DisAdv)
             ]
-        )
+        );
+        assert_eq!(
+            tok_unwrap("d20ad d20adv"),
+            vec![
+                Tok::Roll(1, 20),
+                Tok::Operator(Operator::Adv),
+                Tok::Operator(Operator::DisAdv),
+                Tok::Roll(1, 20),
+                Tok::identifier("adv"),
+            ]
+        );
     }
 
     #[test]
@@ -557,6 +718,24 @@ mod test {
         assert_eq!(tok_unwrap("3.14159"), vec![Tok::Decimal(3.14159)])
     }
 
+    #[test]
+    fn test_tokenise_range() {
+        assert_eq!(
+            tok_unwrap("2..5 1..=3 a..b"),
+            vec![
+                Tok::Natural(2),
+                Tok::Operator(Operator::Range),
+                Tok::Natural(5),
+                Tok::Natural(1),
+                Tok::Operator(Operator::RangeInclusive),
+                Tok::Natural(3),
+                Tok::identifier("a"),
+                Tok::Operator(Operator::Range),
+                Tok::identifier("b"),
+            ]
+        )
+    }
+
     #[test]
     fn test_tokenise_decimal_call() {
         assert_eq!(
@@ -577,6 +756,44 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_tokenise_match() {
+        assert_eq!(
+            tok_unwrap("match x { 1 -> a, _ -> b }"),
+            vec![
+                Tok::identifier("match"),
+                Tok::identifier("x"),
+                Tok::BraceOpen,
+                Tok::Natural(1),
+                Tok::Arrow,
+                Tok::identifier("a"),
+                Tok::Comma,
+                Tok::identifier("_"),
+                Tok::Arrow,
+                Tok::identifier("b"),
+                Tok::BraceClose
+            ]
+        )
+    }
+
+    #[test]
+    fn test_tokenise_map() {
+        assert_eq!(
+            tok_unwrap(r#"{"str": 16, "dex": 14}"#),
+            vec![
+                Tok::BraceOpen,
+                Tok::String("str".into()),
+                Tok::Colon,
+                Tok::Natural(16),
+                Tok::Comma,
+                Tok::String("dex".into()),
+                Tok::Colon,
+                Tok::Natural(14),
+                Tok::BraceClose
+            ]
+        )
+    }
+
     #[test]
     fn test_comment() {
         assert_eq!(
@@ -641,6 +858,45 @@ else
         )
     }
 
+    #[test]
+    fn test_tokenise_string_escapes() {
+        assert_eq!(
+            tok_unwrap(r#""a\rb" "\u{2728}!" "\t\n""#),
+            vec![
+                Tok::String("a\rb".into()),
+                Tok::String("\u{2728}!".into()),
+                Tok::String("\t\n".into())
+            ]
+        );
+        assert_eq!(
+            tokenise(r#""\u{2728}" 1"#).unwrap().tokens[1],
+            Token::new(Tok::Natural(1), 1, 12, 11, 1)
+        );
+        let error = |input| tokenise(input).err().unwrap_or_default();
+        assert!(error(r#""\q""#).ends_with("Invalid escape sequence: \\q"));
+        for malformed in [
+            r#""\u2728""#,
+            r#""\u{}""#,
+            r#""\u{27g8}""#,
+            r#""\u{1234567}""#,
+        ] {
+            assert!(error(malformed).ends_with("Unicode escapes must be of the form \\u{XXXX}."));
+        }
+        assert!(error(r#""\u{d800}""#).ends_with("Invalid unicode code point: d800."));
+    }
+
+    #[test]
+    fn test_unterminated_string_context() {
+        assert_eq!(
+            tokenise("x = 1\ny = \"abc + 2\nz").err(),
+            Some("y = \"abc + 2\n    ^^^^^^^^\nStrings must be single line.".into())
+        );
+        assert_eq!(
+            tokenise("print(\"abc").err(),
+            Some("print(\"abc\n      ^^^^\nUnterminated string.".into())
+        );
+    }
+
     #[test]
     fn test_token_context_1() {
         let tokens = tokenise("if true then").unwrap();
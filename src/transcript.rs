@@ -0,0 +1,101 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::Res;
+
+/// Log of the inputs and outcomes of a session, appended to a file.
+pub struct Transcript {
+    file: File,
+    path: String,
+}
+
+impl Transcript {
+    pub fn open(path: &str) -> Res<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {path}: {e}"))?;
+        Ok(Self {
+            file,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn record(&mut self, input: &str, output: &[String]) -> Res<()> {
+        self.file
+            .write_all(entry(now(), input, output).as_bytes())
+            .map_err(|e| format!("Failed to write to {}: {e}", self.path))
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// UTC date and time of a number of seconds since the Unix epoch.
+pub fn timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // Convert days since the epoch to a civil date, with years starting in
+    // March so that the leap day falls at the end of the year.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Format a log entry of an input and the lines output for it.
+fn entry(seconds: u64, input: &str, output: &[String]) -> String {
+    let mut entry = format!("[{}] > {input}\n", timestamp(seconds));
+    for line in output {
+        entry.push_str(line);
+        entry.push('\n');
+    }
+    entry
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(timestamp(951782400), "2000-02-29 00:00:00");
+        assert_eq!(timestamp(1700000000), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn test_entry() {
+        let output = ["d20\tRolls: \t14\tTotal: 14".to_string(), "19".to_string()];
+        assert_eq!(
+            entry(1700000000, "d20 + 5", &output),
+            "[2023-11-14 22:13:20] > d20 + 5\nd20\tRolls: \t14\tTotal: 14\n19\n"
+        );
+    }
+}
@@ -1,12 +1,72 @@
-use std::{convert::TryInto, fmt::Display};
+use std::{cmp::Ordering, convert::TryInto, fmt::Display};
 
 use crate::{
-    err,
-    roll::{Roll, RollOutcome},
+    err, rng,
+    roll::{Explode, Roll, RollOutcome},
     Res,
 };
 
-#[derive(Clone, Debug, PartialEq)]
+pub const DEFAULT_PRECISION: usize = 2;
+
+// Most decimal places displayed. An f64 holds 15 significant digits, and more
+// places make the rounding factor overflow.
+pub const MAX_PRECISION: usize = 15;
+
+pub const OVERFLOW: &str = "Numeric overflow.";
+
+// Most dice which can be rolled at once.
+const MAX_QUANTITY: u64 = 1_000_000;
+
+// Most elements which can be generated in a list at once, by a range or
+// repeat.
+pub const MAX_LENGTH: i64 = 1_000_000;
+
+// Most times a single die can explode.
+const MAX_EXPLOSIONS: usize = 100;
+
+/// Roll a die of a roll, rolling again while it explodes, and add the results
+/// to values. A die explodes when it rolls at least the roll's threshold, or
+/// its maximum if there is none. Errors if a compounded total is too large to
+/// represent.
+fn roll_die(roll: &Roll, values: &mut Vec<u64>) -> Res<()> {
+    let mut value = rng::roll(roll.die);
+    let Some(explode) = roll.explode else {
+        values.push(value);
+        return Ok(());
+    };
+
+    let threshold = roll.threshold.unwrap_or(roll.die);
+    let mut total = value;
+    let mut explosions = 0;
+    while value >= threshold && explosions < MAX_EXPLOSIONS {
+        if explode == Explode::Separate {
+            values.push(total);
+            total = 0;
+        }
+        value = rng::roll(roll.die);
+        total = total
+            .checked_add(value)
+            .ok_or_else(|| OVERFLOW.to_string())?;
+        explosions += 1;
+    }
+    values.push(total);
+    Ok(())
+}
+
+/// Sum of a list of rolls, or an error if it is too large to represent.
+fn sum(rolls: &[u64]) -> Res<u64> {
+    rolls
+        .iter()
+        .try_fold(0u64, |acc, &roll| acc.checked_add(roll))
+        .ok_or_else(|| OVERFLOW.to_string())
+}
+
+/// Convert a roll total to a natural, or an error if it is too large.
+fn natural(total: u64) -> Res<i64> {
+    total.try_into().map_err(|_| OVERFLOW.to_string())
+}
+
+#[derive(Clone, Debug)]
 pub enum Value {
     Bool(bool),
     Decimal(f64),
@@ -15,16 +75,48 @@ pub enum Value {
     Roll(Roll),
     Rolls(Vec<u64>),
     List(Vec<Value>),
+    Map(Vec<(Value, Value)>), // (Key, value) pairs in insertion order.
     String(String),
+    Function(String),           // Name of a function.
+    Tagged(Box<Value>, String), // Value, damage type.
     Empty,
 }
 
 impl Value {
+    /// Construct a list of the naturals from start up to but not including
+    /// end, or an error if it would be too long.
+    pub fn range(start: i64, end: i64) -> Res<Self> {
+        if end.saturating_sub(start) > MAX_LENGTH {
+            return Err(format!(
+                "Range {start}..{end} is too long, at most {MAX_LENGTH} elements can be generated."
+            ));
+        }
+        Ok(Value::List((start..end).map(Value::Natural).collect()))
+    }
+
+    /// Tag a value with a damage type, replacing any existing tag.
+    pub fn tagged(self, tag: String) -> Self {
+        match self {
+            Value::Tagged(value, _) => Value::Tagged(value, tag),
+            value => Value::Tagged(Box::new(value), tag),
+        }
+    }
+
+    /// Damage type of a tagged value.
+    pub fn tag(&self) -> Option<&str> {
+        match self {
+            Value::Tagged(_, tag) => Some(tag),
+            _ => None,
+        }
+    }
+
     pub fn bool(self) -> Res<bool> {
         match self {
+            Value::Tagged(value, _) => value.bool(),
             Value::Bool(v) => Ok(v),
             Value::Natural(n) => Ok(n != 0),
             Value::List(vs) => Ok(!vs.is_empty()),
+            Value::Map(entries) => Ok(!entries.is_empty()),
             Value::String(s) => Ok(!s.is_empty()),
             _ => Err(format!("{self} cannot be interpreted as a bool.")),
         }
@@ -35,7 +127,7 @@ impl Value {
             Self::Decimal(v) => Ok(v),
             Self::Natural(v) => Ok(v as f64),
             Self::Roll(..) => Self::Rolls(self.rolls()?).decimal(),
-            Self::Rolls(rolls) => Ok(rolls.iter().sum::<u64>() as f64),
+            Self::Rolls(rolls) => Ok(sum(&rolls)? as f64),
             Self::Outcome(outcome) => Ok(outcome.result as f64),
             Self::List(values) => {
                 let mut total = 0.0;
@@ -44,28 +136,38 @@ impl Value {
                 }
                 Ok(total)
             }
-            Self::Bool(v) => Err(format!("{v} cannot be interpreted as decimal.")),
+            Self::Tagged(value, _) => value.decimal(),
+            Self::Map(_) => err("Map cannot be interpreted as decimal."),
+            Self::Bool(v) => Ok(if v { 1.0 } else { 0.0 }),
             Self::String(_) => err("String cannot be interpreted as decimal."),
+            Self::Function(_) => err("Function cannot be interpreted as decimal."),
             Self::Empty => err("Empty cannot be interpreted as decimal."),
         }
     }
 
     pub fn natural(self) -> Res<i64> {
         match self {
-            Self::Decimal(v) => Ok(v as i64),
+            // i64::MAX as f64 rounds up to 2^63, so is itself out of range.
+            Self::Decimal(v) if (i64::MIN as f64..i64::MAX as f64).contains(&v) => Ok(v as i64),
+            Self::Decimal(_) => err(OVERFLOW),
             Self::Natural(v) => Ok(v),
-            Self::Outcome(outcome) => Ok(outcome.result as i64),
-            Self::Roll(_) => Ok(self.outcome()?.result as i64),
-            Self::Rolls(rolls) => Ok(rolls.iter().sum::<u64>() as i64),
+            Self::Outcome(outcome) => natural(outcome.result),
+            Self::Roll(_) => natural(self.outcome()?.result),
+            Self::Rolls(rolls) => natural(sum(&rolls)?),
             Self::List(values) => {
-                let mut total = 0;
+                let mut total: i64 = 0;
                 for value in values {
-                    total += value.natural()?;
+                    total = total
+                        .checked_add(value.natural()?)
+                        .ok_or_else(|| OVERFLOW.to_string())?;
                 }
                 Ok(total)
             }
-            Self::Bool(v) => Err(format!("{v} cannot be interpreted as natural.")),
+            Self::Tagged(value, _) => value.natural(),
+            Self::Map(_) => err("Map cannot be interpreted as natural."),
+            Self::Bool(v) => Ok(v as i64),
             Self::String(_) => err("String cannot be interpreted as natural."),
+            Self::Function(_) => err("Function cannot be interpreted as natural."),
             Self::Empty => err("Empty cannot be interpreted as natural."),
         }
     }
@@ -79,11 +181,81 @@ impl Value {
             Self::Rolls(rolls) => Ok(rolls),
             Self::Outcome(outcome) => Ok(outcome.rolls),
             Self::List(_) => err("List cannot be interpreted as rolls."),
+            Self::Tagged(value, _) => value.rolls(),
+            Self::Map(_) => err("Map cannot be interpreted as rolls."),
             Self::String(_) => err("String cannot be interpreted as rolls."),
+            Self::Function(_) => err("Function cannot be interpreted as rolls."),
             Self::Empty => err("Empty cannot be interpreted as rolls."),
         }
     }
 
+    pub fn map(self) -> Res<Vec<(Value, Value)>> {
+        match self {
+            Value::Map(entries) => Ok(entries),
+            _ => Err(format!("{self} is not a map.")),
+        }
+    }
+
+    pub fn function(self) -> Res<String> {
+        match self {
+            Value::Function(name) => Ok(name),
+            _ => Err(format!("{self} is not a function.")),
+        }
+    }
+
+    /// Whether two values have the same result. Unlike ==, numbers are equal
+    /// if they have the same value regardless of type, so 2 and 2.0 are the
+    /// same, as are a rolled roll and its total. Lists and maps are compared
+    /// element by element.
+    pub fn same_result(&self, other: &Value) -> bool {
+        let numeric = |value: &Value| {
+            matches!(
+                value,
+                Value::Natural(_) | Value::Decimal(_) | Value::Rolls(_) | Value::Outcome(_)
+            )
+        };
+
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_result(b))
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b)
+                        .all(|((ak, av), (bk, bv))| ak.same_result(bk) && av.same_result(bv))
+            }
+            (Value::Tagged(a, at), Value::Tagged(b, bt)) => at == bt && a.same_result(b),
+            (a, b) if numeric(a) && numeric(b) => {
+                matches!((a.clone().decimal(), b.clone().decimal()), (Ok(a), Ok(b)) if a == b)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// Order two values. Strings, bools and lists compare with values of the
+    /// same type, while anything else is compared numerically.
+    pub fn compare(&self, other: &Value) -> Res<Ordering> {
+        match (self, other) {
+            (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Ok(a.cmp(b)),
+            (Value::List(a), Value::List(b)) => {
+                for (a, b) in a.iter().zip(b) {
+                    let ordering = a.compare(b)?;
+                    if ordering != Ordering::Equal {
+                        return Ok(ordering);
+                    }
+                }
+                Ok(a.len().cmp(&b.len()))
+            }
+            _ => {
+                let (a, b) = (self.clone().decimal()?, other.clone().decimal()?);
+                a.partial_cmp(&b)
+                    .ok_or_else(|| format!("Cannot compare {self} and {other}."))
+            }
+        }
+    }
+
     pub fn roll(self) -> Res<Roll> {
         match self {
             Value::Roll(roll) => Ok(roll),
@@ -97,32 +269,36 @@ impl Value {
         }
 
         let roll = self.roll()?;
-        let mut quantity: usize = roll
-            .quantity
+        if roll.die == 0 {
+            return err("Cannot roll a die with 0 sides.");
+        }
+        if roll.explode.is_some() && roll.threshold.unwrap_or(roll.die) <= 1 {
+            return err("Cannot explode a d1, as it always explodes.");
+        }
+        if roll.quantity > MAX_QUANTITY {
+            return Err(format!(
+                "{} is too many dice, at most {MAX_QUANTITY} can be rolled.",
+                roll.quantity
+            ));
+        }
+        let quantity: usize = roll
+            .dice()
             .try_into()
             .map_err(|_| format!("{} is too many dice.", roll.quantity))?;
-        if roll.advantage ^ roll.disadvantage {
-            quantity = quantity.max(2);
-        }
 
         let mut values = Vec::with_capacity(quantity);
-        let die = roll.die;
         for _ in 0..quantity {
-            values.push(rand::Rng::gen_range(&mut rand::thread_rng(), 1..=die))
+            roll_die(&roll, &mut values)?;
         }
 
-        let result = if roll.advantage ^ roll.disadvantage {
-            let mut sorted = values.clone();
-            sorted.sort();
-
-            // Safe because quantity.max(2)
-            if roll.advantage {
-                *values.last().unwrap()
-            } else {
-                *values.first().unwrap()
-            }
+        // Safe to unwrap because at least two dice are rolled with advantage
+        // or disadvantage.
+        let result = if roll.takes_highest() {
+            *values.iter().max().unwrap()
+        } else if roll.takes_lowest() {
+            *values.iter().min().unwrap()
         } else {
-            values.iter().sum()
+            sum(&values)?
         };
 
         Ok(RollOutcome {
@@ -149,21 +325,192 @@ impl Value {
                 .map(|c| Value::String(c.to_string()))
                 .collect()),
             Value::List(values) => Ok(values),
-            Value::Roll(..) | Value::Rolls(..) | Value::Outcome(..) => Ok(self
+            Value::Roll(..) | Value::Rolls(..) | Value::Outcome(..) => self
                 .rolls()?
-                .iter()
-                .map(|v| Self::Natural(*v as i64))
-                .collect()),
+                .into_iter()
+                .map(|v| i64::try_from(v).map(Self::Natural).or(err(OVERFLOW)))
+                .collect(),
             _ => Err(format!("{self} cannot be interpreted as a list.")),
         }
     }
+
+    /// Retrieve the element at an index of a list or the value for a key in
+    /// a map.
+    pub fn get(self, key: Value) -> Res<Value> {
+        match self {
+            Value::Map(entries) => {
+                for (k, v) in entries {
+                    if k == key {
+                        return Ok(v);
+                    }
+                }
+                Err(format!("Key {key} not found in map."))
+            }
+            _ => {
+                let index = key.natural()?;
+                let list = self.list()?;
+                if index < 0 || index as usize >= list.len() {
+                    Err(format!(
+                        "Index {index} out of range for list of length {}.",
+                        list.len()
+                    ))
+                } else {
+                    Ok(list.into_iter().nth(index as usize).unwrap())
+                }
+            }
+        }
+    }
+
+    /// Return a copy of this list or map with the element at the given index
+    /// or key replaced. Setting a key which is not in a map inserts it.
+    pub fn set(self, key: Value, value: Value) -> Res<Value> {
+        match self {
+            Value::Map(mut entries) => {
+                if let Some(entry) = entries.iter_mut().find(|(k, _)| *k == key) {
+                    entry.1 = value;
+                } else {
+                    entries.push((key, value));
+                }
+                Ok(Value::Map(entries))
+            }
+            _ => {
+                let index = key.natural()?;
+                let mut list = self.list()?;
+                if index < 0 || index as usize >= list.len() {
+                    Err(format!(
+                        "Index {index} out of range for list of length {}.",
+                        list.len()
+                    ))
+                } else {
+                    list[index as usize] = value;
+                    Ok(Value::List(list))
+                }
+            }
+        }
+    }
+}
+
+/// Values are equal if they are structurally identical, except that rolled
+/// dice are equal to a list of the same naturals.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Decimal(a), Value::Decimal(b)) => a == b,
+            (Value::Natural(a), Value::Natural(b)) => a == b,
+            (Value::Outcome(a), Value::Outcome(b)) => a == b,
+            (Value::Roll(a), Value::Roll(b)) => a == b,
+            (Value::Rolls(a), Value::Rolls(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Rolls(rolls), Value::List(list)) | (Value::List(list), Value::Rolls(rolls)) => {
+                rolls.len() == list.len()
+                    && rolls.iter().zip(list).all(|(&roll, value)| {
+                        i64::try_from(roll).is_ok_and(|roll| *value == Value::Natural(roll))
+                    })
+            }
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => a == b,
+            (Value::Tagged(a, a_tag), Value::Tagged(b, b_tag)) => a == b && a_tag == b_tag,
+            (Value::Empty, Value::Empty) => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Natural(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Decimal(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Value::List(values)
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Res<Self> {
+        value.natural()
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(value: Value) -> Res<Self> {
+        value.decimal()
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = String;
+
+    fn try_from(value: Value) -> Res<Self> {
+        value.bool()
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(value: Value) -> Res<Self> {
+        value.string()
+    }
+}
+
+/// Displays a value with decimals rounded to a number of places. If the flag
+/// is set, the value is displayed as an expression which evaluates to it.
+struct Formatted<'a>(&'a Value, usize, bool);
+
+/// Format a value with decimals rounded to the given number of places.
+pub fn format_value(value: &Value, precision: usize) -> String {
+    Formatted(value, precision, false).to_string()
+}
+
+impl Value {
+    /// An expression which evaluates to this value, used when saving it.
+    pub fn source(&self) -> String {
+        Formatted(self, DEFAULT_PRECISION, true).to_string()
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
+        Formatted(self, DEFAULT_PRECISION, false).fmt(f)
+    }
+}
+
+impl Display for Formatted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let &Formatted(value, precision, source) = self;
+        match value {
             &Value::Bool(v) => write!(f, "{v}"),
-            &Value::Decimal(v) => write!(f, "{}", (v * 100.0).round() / 100.0), // 2 places.
+            &Value::Decimal(v) => {
+                let factor = 10f64.powi(precision as i32);
+                write!(f, "{}", (v * factor).round() / factor)
+            }
             Value::Natural(v) => write!(f, "{v}"),
             Value::Outcome(v) => write!(f, "{}", v.result),
             Value::Roll(v) => write!(f, "{v}"),
@@ -184,12 +531,39 @@ impl Display for Value {
                     "[{}]",
                     values
                         .iter()
-                        .map(|r| r.to_string())
+                        .map(|v| Formatted(v, precision, source).to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                )
+            }
+            Value::Map(entries) => {
+                write!(
+                    f,
+                    "{{{}}}",
+                    entries
+                        .iter()
+                        .map(|(k, v)| {
+                            format!(
+                                "{}: {}",
+                                Formatted(k, precision, source),
+                                Formatted(v, precision, source)
+                            )
+                        })
                         .collect::<Vec<String>>()
                         .join(", ")
                 )
             }
             Value::String(s) => write!(f, r#""{}""#, s.replace('"', "\\\"")),
+            Value::Function(name) => write!(f, "{name}"),
+            Value::Tagged(value, tag) if source => write!(
+                f,
+                "tag({}, {})",
+                Formatted(value, precision, source),
+                Value::String(tag.clone())
+            ),
+            Value::Tagged(value, tag) => {
+                write!(f, "{} ({tag})", Formatted(value, precision, source))
+            }
             Value::Empty => write!(f, "()"),
         }
     }
@@ -203,7 +577,41 @@ mod test {
 
     fn test_homoiconicity(val: Value) {
         let mut cx = Context::empty();
-        assert_eq!(eval(&val.to_string(), &mut cx).unwrap().value, val);
+        assert_eq!(eval(&val.source(), &mut cx).unwrap().value, val);
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Value::from(3), Value::Natural(3));
+        assert_eq!(Value::from(1.5), Value::Decimal(1.5));
+        assert_eq!(Value::from(true), Value::Bool(true));
+        assert_eq!(Value::from("a".to_string()), Value::String("a".into()));
+        assert_eq!(
+            Value::from(vec![Value::from(1)]),
+            Value::List(vec![Value::Natural(1)])
+        );
+
+        assert_eq!(i64::try_from(Value::Decimal(2.5)), Ok(2));
+        assert_eq!(f64::try_from(Value::Natural(2)), Ok(2.0));
+        assert_eq!(bool::try_from(Value::Natural(0)), Ok(false));
+        assert_eq!(String::try_from(Value::Natural(7)), Ok("7".into()));
+
+        assert!(i64::try_from(Value::String("a".into())).is_err());
+        assert!(f64::try_from(Value::String("a".into())).is_err());
+        assert!(bool::try_from(Value::Decimal(1.0)).is_err());
+        assert!(String::try_from(Value::List(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_tagged_homoiconicity() {
+        test_homoiconicity(Value::Natural(8).tagged("fire".into()));
+        test_homoiconicity(Value::Decimal(2.5).tagged("cold".into()));
+        test_homoiconicity(Value::Natural(1).tagged("\"quoted\"".into()));
+
+        // Saved sessions use the form which parses back.
+        let mut cx = Context::empty();
+        eval("x = tag(8, \"fire\")", &mut cx).unwrap();
+        assert_eq!(cx.dump_to_string().unwrap(), "x = tag(8, \"fire\")\n");
     }
 
     #[test]
@@ -220,6 +628,246 @@ mod test {
         ]));
     }
 
+    #[test]
+    fn test_overflow() {
+        let overflow = Err(OVERFLOW.to_string());
+        assert_eq!(Value::Rolls(vec![u64::MAX, 1]).natural(), overflow);
+        assert_eq!(Value::Rolls(vec![u64::MAX / 2 + 1]).natural(), overflow);
+        assert_eq!(
+            Value::List(vec![Value::Natural(i64::MAX), Value::Natural(1)]).natural(),
+            overflow
+        );
+        assert_eq!(Value::Rolls(vec![u64::MAX / 2; 2]).natural(), overflow);
+        assert_eq!(Value::Rolls(vec![3; 4]).natural(), Ok(12));
+        assert_eq!(Value::Decimal(1e300).natural(), overflow);
+        assert_eq!(Value::Decimal(-1e300).natural(), overflow);
+        assert_eq!(Value::Decimal(f64::NAN).natural(), overflow);
+        assert_eq!(Value::Decimal(-2.5).natural(), Ok(-2));
+
+        let mut roll = Roll::new(1, u64::MAX);
+        roll.explode = Some(Explode::Compound);
+        roll.threshold = Some(2);
+        rng::seed(1);
+        assert_eq!(
+            Value::Roll(roll).outcome().err(),
+            Some(OVERFLOW.to_string())
+        );
+    }
+
+    #[test]
+    fn test_too_many_dice() {
+        assert!(Value::Roll(Roll::new(99999999999, 6)).outcome().is_err());
+        assert!(Value::Roll(Roll::new(MAX_QUANTITY + 1, 6))
+            .outcome()
+            .is_err());
+        let outcome = Value::Roll(Roll::new(MAX_QUANTITY, 1)).outcome().unwrap();
+        assert_eq!(outcome.result, MAX_QUANTITY);
+    }
+
+    #[test]
+    fn test_bool_arithmetic() {
+        let mut cx = Context::empty();
+        let mut eval = |input| eval(input, &mut cx).unwrap().value;
+        assert_eq!(eval("true + true").natural(), Ok(2));
+        assert_eq!(eval("false * 3").natural(), Ok(0));
+        assert_eq!(eval("hits = 0; hits = hits + (4 >= 3)").natural(), Ok(1));
+        assert_eq!(
+            Value::List(vec![
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Bool(true)
+            ])
+            .natural(),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_advantage() {
+        let mut roll = Roll::new(1, 20);
+        for level in 1..=2 {
+            roll.advantage = level;
+            rng::seed(1);
+            let adv = Value::Roll(roll.clone()).outcome().unwrap();
+            assert_eq!(adv.rolls.len(), level as usize + 1);
+            assert_eq!(adv.result, *adv.rolls.iter().max().unwrap());
+
+            // The first dice are the same as with less advantage.
+            rng::seed(1);
+            assert_eq!(rng::roll(20), adv.rolls[0]);
+        }
+        assert_eq!(roll.to_string(), "d20aa");
+
+        roll.advantage = 0;
+        roll.disadvantage = true;
+        let disadv = Value::Roll(roll.clone()).outcome().unwrap();
+        assert_eq!(disadv.rolls.len(), 2);
+        assert_eq!(disadv.result, *disadv.rolls.iter().min().unwrap());
+
+        // Advantage and disadvantage cancel out to a straight roll.
+        roll.advantage = 2;
+        assert_eq!(roll.to_string(), "d20");
+        let straight = Value::Roll(roll).outcome().unwrap();
+        assert_eq!(straight.rolls.len(), 1);
+        assert_eq!(straight.result, straight.rolls[0]);
+    }
+
+    #[test]
+    fn test_zero_sided() {
+        assert!(Value::Roll(Roll::new(1, 0)).outcome().is_err());
+        assert!(Value::Roll(Roll::new(0, 0)).outcome().is_err());
+    }
+
+    #[test]
+    fn test_explode() {
+        let mut roll = Roll::new(2, 1);
+        roll.explode = Some(Explode::Separate);
+        assert!(Value::Roll(roll.clone()).outcome().is_err());
+        roll.explode = Some(Explode::Compound);
+        assert!(Value::Roll(roll.clone()).outcome().is_err());
+
+        // A threshold above the die's maximum means it never explodes.
+        roll.threshold = Some(2);
+        assert_eq!(Value::Roll(roll).outcome().unwrap().rolls, vec![1, 1]);
+
+        let mut separate = Roll::new(10, 2);
+        separate.explode = Some(Explode::Separate);
+        let mut compound = separate.clone();
+        compound.explode = Some(Explode::Compound);
+        rng::seed(1);
+        let separate = Value::Roll(separate).outcome().unwrap();
+        rng::seed(1);
+        let compound = Value::Roll(compound).outcome().unwrap();
+        assert!(separate.rolls.len() > 10);
+        assert!(separate.rolls.iter().all(|&v| v <= 2));
+        assert_eq!(compound.rolls.len(), 10);
+        assert!(compound.rolls.iter().any(|&v| v > 2));
+        assert_eq!(separate.result, compound.result);
+    }
+
+    #[test]
+    fn test_explode_threshold() {
+        // Each die's chain of rolls ends with exactly one which didn't explode.
+        let mut roll = Roll::new(100, 10);
+        roll.explode = Some(Explode::Separate);
+        rng::seed(1);
+        let outcome = Value::Roll(roll.clone()).outcome().unwrap();
+        assert!(outcome.rolls.len() > 100);
+        assert_eq!(outcome.rolls.iter().filter(|&&v| v < 10).count(), 100);
+
+        roll.threshold = Some(8);
+        rng::seed(1);
+        let outcome = Value::Roll(roll).outcome().unwrap();
+        assert!(outcome.rolls.iter().any(|&v| v == 8 || v == 9));
+        assert_eq!(outcome.rolls.iter().filter(|&&v| v < 8).count(), 100);
+    }
+
+    #[test]
+    fn test_format_value() {
+        let value = Value::Decimal(1.23456);
+        assert_eq!(format_value(&value, 0), "1");
+        assert_eq!(format_value(&value, 2), "1.23");
+        assert_eq!(format_value(&value, 4), "1.2346");
+        assert_eq!(format_value(&value, MAX_PRECISION), "1.23456");
+        assert_eq!(value.to_string(), "1.23");
+        assert_eq!(
+            format_value(&Value::List(vec![value, Value::Decimal(0.5)]), 0),
+            "[1, 1]"
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        test_homoiconicity(Value::Map(vec![
+            (Value::String("str".into()), Value::Natural(16)),
+            (
+                Value::String("nested".into()),
+                Value::Map(vec![(Value::Natural(1), Value::List(Vec::new()))]),
+            ),
+        ]));
+    }
+
+    #[test]
+    fn test_map_get_set() {
+        let map = Value::Map(vec![(Value::String("dex".into()), Value::Natural(14))]);
+        assert_eq!(
+            map.clone().get(Value::String("dex".into())),
+            Ok(Value::Natural(14))
+        );
+        assert!(map.clone().get(Value::String("str".into())).is_err());
+        assert_eq!(
+            map.set(Value::String("str".into()), Value::Natural(8)),
+            Ok(Value::Map(vec![
+                (Value::String("dex".into()), Value::Natural(14)),
+                (Value::String("str".into()), Value::Natural(8))
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_negative_naturals() {
+        let list = Value::List(vec![Value::Natural(1), Value::Natural(2)]);
+        let out_of_range = err("Index -1 out of range for list of length 2.");
+        assert_eq!(list.clone().get(Value::Natural(-1)), out_of_range);
+        assert_eq!(
+            list.set(Value::Natural(-1), Value::Natural(3)),
+            out_of_range
+        );
+        assert_eq!(
+            Value::Rolls(vec![4, 2]).get(Value::Natural(-2)),
+            err("Index -2 out of range for list of length 2.")
+        );
+        assert!(Value::Natural(-3).rolls().is_err());
+        assert_eq!(
+            Value::Rolls(vec![u64::MAX]).list(),
+            Err(OVERFLOW.to_string())
+        );
+
+        let mut cx = Context::empty();
+        let mut eval = |input| eval(input, &mut cx).map(|outcome| outcome.value);
+        assert_eq!(eval("-3 + 1").unwrap().natural(), Ok(-2));
+        assert_eq!(eval("int(-2.5) * 2").unwrap().natural(), Ok(-4));
+        assert_eq!(eval("[1, 2][-1]"), out_of_range);
+        assert_eq!(
+            eval("4d6k(-1)"),
+            err("Cannot keep a negative number of dice.")
+        );
+    }
+
+    #[test]
+    fn test_rolls() {
+        let rolls = Value::Rolls(vec![4, 2]);
+        let naturals =
+            |values: &[i64]| Value::List(values.iter().copied().map(Value::Natural).collect());
+        assert_eq!(rolls, naturals(&[4, 2]));
+        assert_eq!(naturals(&[4, 2]), rolls);
+        assert_ne!(rolls, naturals(&[2, 4]));
+        assert_ne!(rolls, naturals(&[4]));
+        assert_ne!(
+            rolls,
+            Value::List(vec![Value::Decimal(4.0), Value::Natural(2)])
+        );
+        assert_eq!(rolls.clone().get(Value::Natural(1)), Ok(Value::Natural(2)));
+
+        // Indexing a roll rolls it, recording the roll.
+        let mut cx = Context::empty();
+        for input in ["get(6d6, 0)", "6d6[0]"] {
+            crate::rng::seed(1);
+            let outcome = eval(input, &mut cx).unwrap();
+            assert_eq!(outcome.rolls.len(), 1);
+            assert_eq!(
+                outcome.value,
+                Value::Natural(outcome.rolls[0].rolls[0] as i64)
+            );
+        }
+        assert_eq!(
+            eval("clamp(3d6, 7, 9) == [7, 7, 7]", &mut cx)
+                .unwrap()
+                .value,
+            Value::Bool(true)
+        );
+    }
+
     #[test]
     fn test_string_as_list() {
         assert_eq!(
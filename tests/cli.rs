@@ -0,0 +1,97 @@
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn spells(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_spells"))
+        .args(args)
+        .output()
+        .expect("Failed to run spells.")
+}
+
+/// Run spells with input piped to stdin.
+fn spells_with_input(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_spells"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run spells.");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Parse a comma separated list of integers.
+fn integers(list: &str) -> Vec<u64> {
+    list.split(", ").map(|n| n.parse().unwrap()).collect()
+}
+
+#[test]
+fn test_json() {
+    let output = spells(&["--json", "2d6"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    // {"value": 7, "rolls": [{"roll": "2d6", "rolls": [3, 4], "result": 7}]}
+    let (value, rest) = stdout
+        .trim()
+        .strip_prefix("{\"value\": ")
+        .and_then(|s| s.split_once(", \"rolls\": [{\"roll\": \"2d6\", \"rolls\": ["))
+        .unwrap();
+    let (dice, result) = rest
+        .strip_suffix("}]}")
+        .and_then(|s| s.split_once("], \"result\": "))
+        .unwrap();
+
+    let dice = integers(dice);
+    assert_eq!(dice.len(), 2);
+    assert!(dice.iter().all(|d| (1..=6).contains(d)));
+    assert_eq!(integers(result), vec![dice.iter().sum()]);
+    assert_eq!(integers(value), integers(result));
+}
+
+#[test]
+fn test_expression() {
+    let output = spells(&["1", "+", "2"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "3\n");
+}
+
+#[test]
+fn test_exit_status() {
+    let output = spells(&["bad ++ expr"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+
+    let output = spells(&["--json", "1 / 0"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Division by zero.\n"
+    );
+
+    // Outcomes of statements before the error are still printed.
+    let output = spells(&["1; 1 / 0"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+
+    assert_eq!(spells(&["--unknown"]).status.code(), Some(2));
+}
+
+#[test]
+fn test_piped_input() {
+    let output = spells_with_input("x = 2 + 3\nx * 2\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "5\n10\n");
+
+    // Commands work, and expressions may continue over lines.
+    let output = spells_with_input(".precision 1\n5 / 2\n[1,\n2]\n");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "2.5\n[1, 2]\n");
+}